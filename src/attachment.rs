@@ -0,0 +1,174 @@
+use crate::prelude::*;
+
+/// Storage format of a color attachment
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorFormat {
+    /// Four 8 bit unsigned normalized channels, values are clamped to [0, 1]
+    Rgba8,
+    /// Four 16 bit float channels
+    Rgba16F,
+    /// Four 32 bit float channels
+    Rgba32F,
+    /// A single 32 bit float channel, only the first component is stored
+    R32F,
+}
+
+/// Values of a color attachment in the storage of its [`ColorFormat`]
+enum AttachmentData {
+    Rgba8(Vec<[u8; 4]>),
+    Rgba16F(Vec<[u16; 4]>),
+    Rgba32F(Vec<Vec4>),
+    R32F(Vec<f32>),
+}
+
+/// An additional color buffer of a framebuffer. Every value is read and
+/// written as a [`Vec4`] and converted to the format of the attachment.
+pub struct ColorAttachment {
+    data: AttachmentData,
+    clear_value: Vec4,
+}
+
+impl ColorAttachment {
+    /// Creates an attachment with `size` values that are set to the clear
+    /// value
+    pub fn new(format: ColorFormat, size: usize, clear_value: Vec4) -> Self {
+        let data = match format {
+            ColorFormat::Rgba8 => AttachmentData::Rgba8(vec![[0; 4]; size]),
+            ColorFormat::Rgba16F => AttachmentData::Rgba16F(vec![[0; 4]; size]),
+            ColorFormat::Rgba32F => {
+                AttachmentData::Rgba32F(vec![Vec4::ZERO; size])
+            }
+            ColorFormat::R32F => AttachmentData::R32F(vec![0.0; size]),
+        };
+        let mut attachment = Self { data, clear_value };
+        attachment.clear();
+        attachment
+    }
+
+    pub fn format(&self) -> ColorFormat {
+        match self.data {
+            AttachmentData::Rgba8(_) => ColorFormat::Rgba8,
+            AttachmentData::Rgba16F(_) => ColorFormat::Rgba16F,
+            AttachmentData::Rgba32F(_) => ColorFormat::Rgba32F,
+            AttachmentData::R32F(_) => ColorFormat::R32F,
+        }
+    }
+
+    /// Returns the value the attachment is cleared to
+    pub fn clear_value(&self) -> Vec4 {
+        self.clear_value
+    }
+
+    /// Sets all values to the clear value
+    pub fn clear(&mut self) {
+        let value = self.clear_value;
+        match &mut self.data {
+            AttachmentData::Rgba8(data) => data.fill(to_rgba8(value)),
+            AttachmentData::Rgba16F(data) => data.fill(to_rgba16f(value)),
+            AttachmentData::Rgba32F(data) => data.fill(value),
+            AttachmentData::R32F(data) => data.fill(value.x),
+        }
+    }
+
+    /// Changes the number of values, new values are set to the clear value
+    pub fn resize(&mut self, size: usize) {
+        let value = self.clear_value;
+        match &mut self.data {
+            AttachmentData::Rgba8(data) => data.resize(size, to_rgba8(value)),
+            AttachmentData::Rgba16F(data) => {
+                data.resize(size, to_rgba16f(value))
+            }
+            AttachmentData::Rgba32F(data) => data.resize(size, value),
+            AttachmentData::R32F(data) => data.resize(size, value.x),
+        }
+    }
+
+    /// Returns the value at the given index. Missing channels are 0, except
+    /// for alpha which is 1.
+    pub fn get(&self, idx: usize) -> Vec4 {
+        match &self.data {
+            AttachmentData::Rgba8(data) => {
+                Vec4::from_array(data[idx].map(|c| c as f32 / 255.0))
+            }
+            AttachmentData::Rgba16F(data) => {
+                Vec4::from_array(data[idx].map(f16_to_f32))
+            }
+            AttachmentData::Rgba32F(data) => data[idx],
+            AttachmentData::R32F(data) => Vec4::new(data[idx], 0.0, 0.0, 1.0),
+        }
+    }
+
+    /// Sets the value at the given index
+    pub fn set(&mut self, idx: usize, value: Vec4) {
+        match &mut self.data {
+            AttachmentData::Rgba8(data) => data[idx] = to_rgba8(value),
+            AttachmentData::Rgba16F(data) => data[idx] = to_rgba16f(value),
+            AttachmentData::Rgba32F(data) => data[idx] = value,
+            AttachmentData::R32F(data) => data[idx] = value.x,
+        }
+    }
+}
+
+fn to_rgba8(value: Vec4) -> [u8; 4] {
+    value
+        .to_array()
+        .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn to_rgba16f(value: Vec4) -> [u16; 4] {
+    value.to_array().map(f32_to_f16)
+}
+
+/// Converts a float to the bits of a half precision float. Values that are
+/// too large become infinity.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    // Infinity and NaN
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal half, or zero if it is too small
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = (mantissa + (1 << (shift - 1))) >> shift;
+        return sign | half as u16;
+    }
+
+    // Rounding may carry into the exponent, which is still correct
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let round = (mantissa >> 12) & 1;
+    sign | (half + round) as u16
+}
+
+/// Converts the bits of a half precision float to a float
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits & 0x8000) as u32) << 16;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+    match exponent {
+        0 => {
+            let value = mantissa as f32 / (1 << 24) as f32;
+            if sign != 0 {
+                -value
+            } else {
+                value
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
@@ -0,0 +1,132 @@
+use crate::prelude::*;
+
+/// Axis aligned bounding box
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Returns the smallest box that contains all points or `None` if there
+    /// are no points
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        let mut aabb = Self {
+            min: first,
+            max: first,
+        };
+        for p in points {
+            aabb.min = aabb.min.min(p);
+            aabb.max = aabb.max.max(p);
+        }
+        Some(aabb)
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Returns the eight corners of the box
+    pub fn corners(&self) -> [Vec3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vec3::new(min.x, min.y, min.z),
+            Vec3::new(max.x, min.y, min.z),
+            Vec3::new(min.x, max.y, min.z),
+            Vec3::new(max.x, max.y, min.z),
+            Vec3::new(min.x, min.y, max.z),
+            Vec3::new(max.x, min.y, max.z),
+            Vec3::new(min.x, max.y, max.z),
+            Vec3::new(max.x, max.y, max.z),
+        ]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Returns a sphere around the center of the box that contains all
+    /// points
+    pub fn from_points(points: &[Vec3], aabb: &Aabb) -> Self {
+        let center = aabb.center();
+        let radius = points
+            .iter()
+            .map(|p| p.distance_squared(center))
+            .fold(0.0, f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+}
+
+/// The six planes of the view volume of a camera. The plane normals point to
+/// the inside.
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    /// Planes as (normal, distance), a point p is inside if
+    /// `normal.dot(p) + distance >= 0`
+    planes: [Vec4; 6],
+    view_proj: Mat4,
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix
+    pub fn from_view_proj(view_proj: Mat4) -> Self {
+        let r0 = view_proj.row(0);
+        let r1 = view_proj.row(1);
+        let r2 = view_proj.row(2);
+        let r3 = view_proj.row(3);
+
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 + r2, r3 - r2]
+            .map(|plane| plane / plane.truncate().length());
+        Self { planes, view_proj }
+    }
+
+    /// Extracts the planes of a view projection matrix with a reversed
+    /// [0, 1] depth range, where the near plane is at a depth of 1 and the
+    /// far plane at 0
+    pub fn from_reversed_view_proj(view_proj: Mat4) -> Self {
+        let r0 = view_proj.row(0);
+        let r1 = view_proj.row(1);
+        let r2 = view_proj.row(2);
+        let r3 = view_proj.row(3);
+
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 - r2, r2]
+            .map(|plane| plane / plane.truncate().length());
+        Self { planes, view_proj }
+    }
+
+    /// Returns the view projection matrix the planes were extracted from
+    pub fn view_proj(&self) -> Mat4 {
+        self.view_proj
+    }
+
+    /// Returns false if the sphere is completely outside of the frustum
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| {
+            plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius
+        })
+    }
+
+    /// Returns false if the box is completely outside of the frustum
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner that is furthest along the plane normal
+            let normal = plane.truncate();
+            let p = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(p) + plane.w >= 0.0
+        })
+    }
+
+    /// Returns false if the mesh is completely outside of the frustum. The
+    /// cheap sphere test runs first and the box test refines it.
+    pub fn intersects_mesh(&self, mesh: &Mesh) -> bool {
+        self.intersects_sphere(mesh.bounding_sphere())
+            && self.intersects_aabb(mesh.aabb())
+    }
+}
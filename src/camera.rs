@@ -2,6 +2,28 @@ use crate::prelude::*;
 
 use std::f32::consts::PI;
 
+/// How the camera projects the scene onto the screen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    Perspective,
+    /// Parallel projection. `height` is the height of the view volume at a
+    /// zoom of 1.
+    Orthographic {
+        height: f32,
+    },
+}
+
+/// Predefined directions to look at a target from
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ViewPreset {
+    /// Looking along the negative z axis
+    Front,
+    /// Looking along the negative x axis
+    Side,
+    /// Looking down along the negative y axis
+    Top,
+}
+
 pub struct Camera {
     front: Vec3,
     right: Vec3,
@@ -11,8 +33,27 @@ pub struct Camera {
     pitch: f32,
 
     position: Vec3,
+
+    /// Vertical field of view in radians
+    fov_y: f32,
+    aspect_ratio: f32,
+    near: f32,
+    far: f32,
+
+    projection: Projection,
+    zoom: f32,
+    /// Project the near plane to a depth of 1 and the far plane to 0
+    reversed_z: bool,
 }
 
+/// Maps the [0, 1] depth range of a projection to [1, 0], keeping `w`
+const REVERSE_Z: Mat4 = Mat4::from_cols(
+    Vec4::X,
+    Vec4::Y,
+    Vec4::new(0.0, 0.0, -1.0, 0.0),
+    Vec4::new(0.0, 0.0, 1.0, 1.0),
+);
+
 impl Camera {
     pub fn new(position: Vec3) -> Self {
         Self {
@@ -22,6 +63,13 @@ impl Camera {
             yaw: -(PI / 2.0),
             pitch: 0.0,
             position,
+            fov_y: PI / 3.0,
+            aspect_ratio: 1.0,
+            near: 0.1,
+            far: 100.0,
+            projection: Projection::Perspective,
+            zoom: 1.0,
+            reversed_z: false,
         }
     }
 
@@ -41,10 +89,26 @@ impl Camera {
         self.position += self.right * delta;
     }
 
+    /// Moves the camera up along the world up axis
+    pub fn move_up(&mut self, delta: f32) {
+        self.position += Vec3::Y * delta;
+    }
+
+    /// Moves the camera down along the world up axis
+    pub fn move_down(&mut self, delta: f32) {
+        self.position -= Vec3::Y * delta;
+    }
+
     pub fn rotate(&mut self, yaw_delta: f32, pitch_delta: f32) {
-        self.yaw += yaw_delta;
+        self.set_yaw_pitch(self.yaw + yaw_delta, self.pitch + pitch_delta);
+    }
+
+    /// Sets the orientation of the camera. The pitch is constrained, so that
+    /// the camera can't look straight up or down.
+    pub fn set_yaw_pitch(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw;
 
-        self.pitch += pitch_delta;
+        self.pitch = pitch;
         let pitch_constrain = (PI / 2.0) - 0.001;
         if self.pitch > pitch_constrain {
             self.pitch = pitch_constrain;
@@ -65,12 +129,218 @@ impl Camera {
         self.up = self.right.cross(self.front).normalize();
     }
 
+    /// Places the camera at `position` and points it at `target`. `up`
+    /// determines the roll of the camera.
+    pub fn look_at(&mut self, position: Vec3, target: Vec3, up: Vec3) {
+        self.position = position;
+        self.front = (target - position).normalize();
+        self.right = self.front.cross(up).normalize();
+        self.up = self.right.cross(self.front).normalize();
+
+        // Keep yaw and pitch in sync, so that rotating continues from the
+        // new orientation
+        self.yaw = self.front.z.atan2(self.front.x);
+        self.pitch = self.front.y.clamp(-1.0, 1.0).asin();
+    }
+
     pub fn view_mat(&self) -> Mat4 {
         let center = self.position + self.front;
         Mat4::look_at_rh(self.position, center, self.up)
     }
 
+    /// Places the camera `distance` away from `target`, looking at it from
+    /// the direction of the preset. The distance is at least the near plane
+    /// distance, so the camera doesn't end up on the target.
+    pub fn set_view_preset(
+        &mut self,
+        preset: ViewPreset,
+        target: Vec3,
+        distance: f32,
+    ) {
+        let (direction, up) = match preset {
+            ViewPreset::Front => (Vec3::Z, Vec3::Y),
+            ViewPreset::Side => (Vec3::X, Vec3::Y),
+            ViewPreset::Top => (Vec3::Y, -Vec3::Z),
+        };
+        let distance = distance.max(self.near);
+        self.look_at(target + direction * distance, target, up);
+    }
+
+    /// Returns the projection matrix. The depth of the near and far plane
+    /// in normalized device coordinates is [`Camera::ndc_depth_range`].
+    pub fn proj_mat(&self) -> Mat4 {
+        if self.reversed_z {
+            return REVERSE_Z * self.proj_mat_zero_to_one();
+        }
+        match self.projection {
+            Projection::Perspective => Mat4::perspective_rh_gl(
+                self.fov_y,
+                self.aspect_ratio,
+                self.near,
+                self.far,
+            ),
+            Projection::Orthographic { height } => {
+                let half_height = height / self.zoom * 0.5;
+                let half_width = half_height * self.aspect_ratio;
+                Mat4::orthographic_rh_gl(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.near,
+                    self.far,
+                )
+            }
+        }
+    }
+
+    /// Projection that maps the near plane to a depth of 0 and the far plane
+    /// to 1
+    fn proj_mat_zero_to_one(&self) -> Mat4 {
+        match self.projection {
+            Projection::Perspective => Mat4::perspective_rh(
+                self.fov_y,
+                self.aspect_ratio,
+                self.near,
+                self.far,
+            ),
+            Projection::Orthographic { height } => {
+                let half_height = height / self.zoom * 0.5;
+                let half_width = half_height * self.aspect_ratio;
+                Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.near,
+                    self.far,
+                )
+            }
+        }
+    }
+
+    /// Returns the depth of the near and the far plane in normalized device
+    /// coordinates
+    pub fn ndc_depth_range(&self) -> (f32, f32) {
+        if self.reversed_z {
+            (1.0, 0.0)
+        } else {
+            (-1.0, 1.0)
+        }
+    }
+
+    /// Returns the view frustum with the planes of the projection
+    pub fn frustum(&self) -> Frustum {
+        if self.reversed_z {
+            Frustum::from_reversed_view_proj(self.view_proj_mat())
+        } else {
+            Frustum::from_view_proj(self.view_proj_mat())
+        }
+    }
+
+    /// Returns the projection matrix multiplied with the view matrix
+    pub fn view_proj_mat(&self) -> Mat4 {
+        self.proj_mat() * self.view_mat()
+    }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+    }
+
     pub fn position(&self) -> Vec3 {
         self.position
     }
+
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    /// Returns the direction the camera is looking at
+    pub fn front(&self) -> Vec3 {
+        self.front
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.up
+    }
+
+    /// Set the vertical field of view in radians
+    pub fn set_fov_y(&mut self, fov_y: f32) {
+        self.fov_y = fov_y;
+    }
+
+    pub fn fov_y(&self) -> f32 {
+        self.fov_y
+    }
+
+    /// Set the aspect ratio from the size of the framebuffer. Sizes with a
+    /// zero dimension, e.g. of a minimized window, are ignored.
+    pub fn set_viewport_size(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.aspect_ratio = width as f32 / height as f32;
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.aspect_ratio
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Switches to a projection that stores the near plane at a depth of 1
+    /// and the far plane at 0 in [0, 1] clip space, which spreads the
+    /// precision of floating point depth buffers evenly. Has to match
+    /// [`DepthState::reversed_z`] of the rasterizer.
+    pub fn set_reversed_z(&mut self, reversed_z: bool) {
+        self.reversed_z = reversed_z;
+    }
+
+    pub fn reversed_z(&self) -> bool {
+        self.reversed_z
+    }
+
+    /// Set the zoom factor. It scales the view volume of the orthographic
+    /// projection, a zoom of 2 halves its size.
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.max(f32::EPSILON);
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    /// Returns the height of the visible area at the given distance in front
+    /// of the camera
+    pub fn view_height(&self, distance: f32) -> f32 {
+        match self.projection {
+            Projection::Perspective => {
+                2.0 * distance * (self.fov_y * 0.5).tan()
+            }
+            Projection::Orthographic { height } => height / self.zoom,
+        }
+    }
+
+    /// Set the distances of the near and far clipping planes
+    pub fn set_clip_planes(&mut self, near: f32, far: f32) {
+        self.near = near;
+        self.far = far;
+    }
+
+    pub fn near(&self) -> f32 {
+        self.near
+    }
+
+    pub fn far(&self) -> f32 {
+        self.far
+    }
 }
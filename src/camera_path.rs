@@ -0,0 +1,232 @@
+use crate::prelude::*;
+
+use anyhow::{anyhow, Context};
+use std::f32::consts::TAU;
+use std::fmt::Write as _;
+use std::ops::{Add, Mul, Sub};
+
+/// State of the camera at a point in time
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    /// Time in seconds
+    pub time: f32,
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Vertical field of view in radians
+    pub fov_y: f32,
+}
+
+impl CameraKeyframe {
+    /// Captures the current state of the camera
+    pub fn from_camera(camera: &Camera, time: f32) -> Self {
+        Self {
+            time,
+            position: camera.position(),
+            yaw: camera.yaw(),
+            pitch: camera.pitch(),
+            fov_y: camera.fov_y(),
+        }
+    }
+
+    /// Moves and rotates the camera to the state of the keyframe
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.set_position(self.position);
+        camera.set_yaw_pitch(self.yaw, self.pitch);
+        camera.set_fov_y(self.fov_y);
+    }
+}
+
+/// A sequence of camera keyframes that are interpolated with a Catmull-Rom
+/// spline. Keyframes don't need to be evenly spaced in time.
+#[derive(Default, Clone, Debug)]
+pub struct CameraPath {
+    keyframes: Vec<CameraKeyframe>,
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a keyframe. Keyframes are kept sorted by time and the yaw is
+    /// unwrapped, so the camera turns the short way to the new keyframe.
+    pub fn add_keyframe(&mut self, mut keyframe: CameraKeyframe) {
+        let idx = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        if idx > 0 {
+            let previous_yaw = self.keyframes[idx - 1].yaw;
+            keyframe.yaw += ((previous_yaw - keyframe.yaw) / TAU).round() * TAU;
+        }
+        self.keyframes.insert(idx, keyframe);
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+    }
+
+    /// Returns the time of the first keyframe
+    pub fn start_time(&self) -> f32 {
+        self.keyframes.first().map_or(0.0, |k| k.time)
+    }
+
+    /// Returns the time of the last keyframe
+    pub fn end_time(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    /// Returns the interpolated camera state at the given time. Times outside
+    /// of the path are clamped to the first or last keyframe. Returns `None`
+    /// if the path is empty or the time is not finite.
+    pub fn sample(&self, time: f32) -> Option<CameraKeyframe> {
+        if !time.is_finite() {
+            return None;
+        }
+        let first = self.keyframes.first()?;
+        let last = self.keyframes.last()?;
+        if time <= first.time {
+            return Some(CameraKeyframe { time, ..*first });
+        }
+        if time >= last.time {
+            return Some(CameraKeyframe { time, ..*last });
+        }
+
+        // Segment between the keyframes i and i + 1
+        let i = self
+            .keyframes
+            .partition_point(|k| k.time <= time)
+            .checked_sub(1)?;
+        let k0 = &self.keyframes[i.saturating_sub(1)];
+        let k1 = &self.keyframes[i];
+        let k2 = &self.keyframes[i + 1];
+        let k3 = &self.keyframes[(i + 2).min(self.keyframes.len() - 1)];
+
+        let t = [k0.time, k1.time, k2.time, k3.time];
+        Some(CameraKeyframe {
+            time,
+            position: catmull_rom(
+                [k0.position, k1.position, k2.position, k3.position],
+                t,
+                time,
+            ),
+            yaw: catmull_rom([k0.yaw, k1.yaw, k2.yaw, k3.yaw], t, time),
+            pitch: catmull_rom(
+                [k0.pitch, k1.pitch, k2.pitch, k3.pitch],
+                t,
+                time,
+            ),
+            fov_y: catmull_rom(
+                [k0.fov_y, k1.fov_y, k2.fov_y, k3.fov_y],
+                t,
+                time,
+            ),
+        })
+    }
+
+    /// Writes the path to a text file with one keyframe per line:
+    /// `time x y z yaw pitch fov_y`
+    pub fn save(&self, file_path: &str) -> Result<()> {
+        let mut text = String::from("# time x y z yaw pitch fov_y\n");
+        for k in &self.keyframes {
+            writeln!(
+                text,
+                "{} {} {} {} {} {} {}",
+                k.time,
+                k.position.x,
+                k.position.y,
+                k.position.z,
+                k.yaw,
+                k.pitch,
+                k.fov_y
+            )?;
+        }
+        std::fs::write(file_path, text)
+            .with_context(|| format!("Could not write {}", file_path))?;
+        Ok(())
+    }
+
+    /// Reads a path that was written with [`CameraPath::save`]
+    pub fn load(file_path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(file_path)
+            .with_context(|| format!("Could not read {}", file_path))?;
+
+        let mut path = Self::new();
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| {
+                    format!("{}:{}: Invalid number", file_path, line_idx + 1)
+                })?;
+            if values.len() != 7 {
+                return Err(anyhow!(
+                    "{}:{}: Expected 7 values but got {}",
+                    file_path,
+                    line_idx + 1,
+                    values.len()
+                ));
+            }
+            if !values.iter().all(|value| value.is_finite()) {
+                return Err(anyhow!(
+                    "{}:{}: Values have to be finite",
+                    file_path,
+                    line_idx + 1
+                ));
+            }
+
+            // Keyframes are stored as they were recorded, so the yaw must
+            // not be unwrapped again
+            let keyframe = CameraKeyframe {
+                time: values[0],
+                position: Vec3::new(values[1], values[2], values[3]),
+                yaw: values[4],
+                pitch: values[5],
+                fov_y: values[6],
+            };
+            let idx =
+                path.keyframes.partition_point(|k| k.time <= keyframe.time);
+            path.keyframes.insert(idx, keyframe);
+        }
+        Ok(path)
+    }
+}
+
+/// Evaluates the Catmull-Rom segment between `p[1]` and `p[2]` at `time`.
+/// The tangents take the time between the keyframes into account.
+fn catmull_rom<T>(p: [T; 4], t: [f32; 4], time: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    // At the ends of the path the neighbouring keyframe is repeated, which
+    // gives a one sided difference. The time span is never zero, because
+    // `time` lies strictly between t[1] and t[2].
+    let tangent = |a: usize, b: usize| (p[b] - p[a]) * (1.0 / (t[b] - t[a]));
+    let m1 = tangent(0, 2);
+    let m2 = tangent(1, 3);
+
+    let h = t[2] - t[1];
+    let s = (time - t[1]) / h;
+    let s2 = s * s;
+    let s3 = s2 * s;
+
+    // Hermite basis functions
+    let h00 = 2.0 * s3 - 3.0 * s2 + 1.0;
+    let h10 = s3 - 2.0 * s2 + s;
+    let h01 = -2.0 * s3 + 3.0 * s2;
+    let h11 = s3 - s2;
+
+    p[1] * h00 + m1 * (h10 * h) + p[2] * h01 + m2 * (h11 * h)
+}
@@ -0,0 +1,294 @@
+use crate::prelude::*;
+
+use winit::event::VirtualKeyCode;
+use winit_input_helper::WinitInputHelper;
+
+/// Mouse button that has to be held to look around
+const LOOK_BUTTON: usize = 1;
+
+/// Moves a [`Camera`] like a free flying camera. WASD moves, E and Q move up
+/// and down, shift sprints and moving the mouse while the right mouse button
+/// is held looks around. With an orthographic projection the mouse wheel
+/// zooms.
+pub struct FlyController {
+    /// Movement speed in units per second
+    speed: f32,
+    sprint_multiplier: f32,
+    /// Rotation in radians per pixel of mouse movement
+    sensitivity: f32,
+    invert_y: bool,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlyController {
+    pub fn new() -> Self {
+        Self {
+            speed: 2.0,
+            sprint_multiplier: 3.0,
+            sensitivity: 0.004,
+            invert_y: false,
+        }
+    }
+
+    /// Set the movement speed in units per second
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// Set the factor the speed is multiplied with while sprinting
+    pub fn set_sprint_multiplier(&mut self, sprint_multiplier: f32) {
+        self.sprint_multiplier = sprint_multiplier;
+    }
+
+    /// Set the rotation in radians per pixel of mouse movement
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.sensitivity = sensitivity;
+    }
+
+    /// Invert the vertical mouse axis
+    pub fn set_invert_y(&mut self, invert_y: bool) {
+        self.invert_y = invert_y;
+    }
+
+    /// Moves and rotates the camera according to the input of the last
+    /// frame. `delta_time` is the duration of the last frame in seconds.
+    pub fn update(
+        &self,
+        camera: &mut Camera,
+        input: &WinitInputHelper,
+        delta_time: f32,
+    ) {
+        zoom_orthographic(camera, input.scroll_diff());
+
+        if input.mouse_held(LOOK_BUTTON) {
+            let (dx, dy) = input.mouse_diff();
+            let dy = if self.invert_y { dy } else { -dy };
+            camera.rotate(dx * self.sensitivity, dy * self.sensitivity);
+        }
+
+        let mut speed = self.speed;
+        if input.held_shift() {
+            speed *= self.sprint_multiplier;
+        }
+        let distance = speed * delta_time;
+
+        if input.key_held(VirtualKeyCode::W) {
+            camera.move_forward(distance);
+        }
+        if input.key_held(VirtualKeyCode::S) {
+            camera.move_backward(distance);
+        }
+        if input.key_held(VirtualKeyCode::A) {
+            camera.move_left(distance);
+        }
+        if input.key_held(VirtualKeyCode::D) {
+            camera.move_right(distance);
+        }
+        if input.key_held(VirtualKeyCode::E) {
+            camera.move_up(distance);
+        }
+        if input.key_held(VirtualKeyCode::Q) {
+            camera.move_down(distance);
+        }
+    }
+}
+
+/// Mouse button that has to be held to orbit or pan
+const ORBIT_BUTTON: usize = 0;
+
+/// Rotates a [`Camera`] around a target point. Dragging with the left mouse
+/// button orbits, dragging with shift held pans and the mouse wheel zooms.
+pub struct OrbitController {
+    target: Vec3,
+    distance: f32,
+    /// Rotation from camera space to world space
+    orientation: Quat,
+    arcball: bool,
+    /// Rotation in radians per pixel of mouse movement
+    sensitivity: f32,
+    /// Fraction of the distance that one step of the mouse wheel zooms
+    zoom_speed: f32,
+    min_distance: f32,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        Self::new(Vec3::ZERO, 3.0)
+    }
+}
+
+impl OrbitController {
+    /// Create a controller that looks at `target` from `distance` along the
+    /// positive z axis
+    pub fn new(target: Vec3, distance: f32) -> Self {
+        Self {
+            target,
+            distance,
+            orientation: Quat::IDENTITY,
+            arcball: false,
+            sensitivity: 0.01,
+            zoom_speed: 0.1,
+            min_distance: 0.01,
+        }
+    }
+
+    /// Set the point to orbit around
+    pub fn set_target(&mut self, target: Vec3) {
+        self.target = target;
+    }
+
+    pub fn target(&self) -> Vec3 {
+        self.target
+    }
+
+    /// Use arcball rotation instead of turntable rotation. The arcball rolls
+    /// the camera freely, while the turntable keeps the horizon level.
+    pub fn set_arcball(&mut self, arcball: bool) {
+        self.arcball = arcball;
+    }
+
+    pub fn arcball(&self) -> bool {
+        self.arcball
+    }
+
+    /// Set the rotation in radians per pixel of mouse movement
+    pub fn set_sensitivity(&mut self, sensitivity: f32) {
+        self.sensitivity = sensitivity;
+    }
+
+    /// Set the fraction of the distance one step of the mouse wheel zooms
+    pub fn set_zoom_speed(&mut self, zoom_speed: f32) {
+        self.zoom_speed = zoom_speed;
+    }
+
+    /// Takes over the position and orientation of the camera. The target is
+    /// kept, so the camera turns towards it.
+    pub fn attach(&mut self, camera: &mut Camera) {
+        let offset = camera.position() - self.target;
+        self.distance = offset.length().max(self.min_distance);
+
+        let view =
+            Mat4::look_at_rh(camera.position(), self.target, camera.up());
+        let (_, rotation, _) = view.inverse().to_scale_rotation_translation();
+        self.orientation = rotation.normalize();
+        self.apply(camera);
+    }
+
+    /// Orbits, pans and zooms the camera according to the input of the last
+    /// frame. `window_size` is used to map the mouse onto the arcball.
+    pub fn update(
+        &mut self,
+        camera: &mut Camera,
+        input: &WinitInputHelper,
+        window_size: (u32, u32),
+    ) {
+        let (dx, dy) = input.mouse_diff();
+
+        if input.mouse_held(ORBIT_BUTTON) && (dx != 0.0 || dy != 0.0) {
+            if input.held_shift() {
+                let view_height = camera.view_height(self.distance);
+                self.pan(dx, dy, view_height, window_size.1);
+            } else if self.arcball {
+                if let Some((x, y)) = input.mouse() {
+                    let previous = (x - dx, y - dy);
+                    self.rotate_arcball(previous, (x, y), window_size);
+                }
+            } else {
+                self.rotate_turntable(dx, dy);
+            }
+        }
+
+        // Moving closer has no effect with an orthographic projection, so
+        // the view volume is scaled instead
+        let scroll = input.scroll_diff();
+        if let Projection::Orthographic { .. } = camera.projection() {
+            zoom_orthographic(camera, scroll);
+        } else if scroll != 0.0 {
+            self.distance *= (1.0 - self.zoom_speed).powf(scroll);
+            self.distance = self.distance.max(self.min_distance);
+        }
+
+        self.apply(camera);
+    }
+
+    /// Rotates around the world up axis and the camera's right axis
+    fn rotate_turntable(&mut self, dx: f32, dy: f32) {
+        let yaw = Quat::from_rotation_y(-dx * self.sensitivity);
+        let pitch = Quat::from_rotation_x(-dy * self.sensitivity);
+        let orientation = (yaw * self.orientation * pitch).normalize();
+
+        // Don't allow to flip over the poles
+        let up = orientation * Vec3::Y;
+        if up.y > 0.001 {
+            self.orientation = orientation;
+        } else {
+            self.orientation = (yaw * self.orientation).normalize();
+        }
+    }
+
+    /// Rotates the camera as if the mouse dragged a sphere around the target
+    fn rotate_arcball(
+        &mut self,
+        from: (f32, f32),
+        to: (f32, f32),
+        window_size: (u32, u32),
+    ) {
+        let from = arcball_point(from, window_size);
+        let to = arcball_point(to, window_size);
+
+        // Turning the scene one way is turning the camera the other way
+        let rotation = Quat::from_rotation_arc(to, from);
+        self.orientation = (self.orientation * rotation).normalize();
+    }
+
+    /// Moves the target in the view plane, so that the point under the mouse
+    /// follows it
+    fn pan(&mut self, dx: f32, dy: f32, view_height: f32, window_height: u32) {
+        let units_per_pixel = view_height / window_height.max(1) as f32;
+
+        let right = self.orientation * Vec3::X;
+        let up = self.orientation * Vec3::Y;
+        self.target += (-right * dx + up * dy) * units_per_pixel;
+    }
+
+    fn apply(&self, camera: &mut Camera) {
+        let position = self.target + self.orientation * Vec3::Z * self.distance;
+        camera.look_at(position, self.target, self.orientation * Vec3::Y);
+    }
+}
+
+/// Scales the view volume of an orthographic camera by the scrolled amount
+fn zoom_orthographic(camera: &mut Camera, scroll: f32) {
+    if let Projection::Orthographic { .. } = camera.projection() {
+        if scroll != 0.0 {
+            camera.set_zoom(camera.zoom() * 1.1_f32.powf(scroll));
+        }
+    }
+}
+
+/// Projects a mouse position onto the unit sphere that fills the window
+fn arcball_point(pos: (f32, f32), window_size: (u32, u32)) -> Vec3 {
+    let (width, height) = (window_size.0 as f32, window_size.1 as f32);
+    let size = width.min(height).max(1.0);
+    let x = (2.0 * pos.0 - width) / size;
+    let y = (height - 2.0 * pos.1) / size;
+
+    let length_squared = x * x + y * y;
+    if length_squared <= 1.0 {
+        Vec3::new(x, y, (1.0 - length_squared).sqrt())
+    } else {
+        Vec3::new(x, y, 0.0).normalize()
+    }
+}
+
+/// The controller that moves the camera
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    Fly,
+    Orbit,
+}
@@ -0,0 +1,200 @@
+use crate::prelude::*;
+
+/// A face of a [`Cubemap`], in the order the faces are stored and loaded
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    /// Returns the face a direction points at and the texture coordinate in
+    /// [0, 1] where it hits the face. Faces are oriented like in OpenGL, with
+    /// the first row of an image at the top.
+    pub fn from_direction(direction: Vec3) -> (CubeFace, Vec2) {
+        let abs = direction.abs();
+        let (face, major, s, t) = if abs.x >= abs.y && abs.x >= abs.z {
+            if direction.x > 0.0 {
+                (CubeFace::PositiveX, abs.x, -direction.z, -direction.y)
+            } else {
+                (CubeFace::NegativeX, abs.x, direction.z, -direction.y)
+            }
+        } else if abs.y >= abs.z {
+            if direction.y > 0.0 {
+                (CubeFace::PositiveY, abs.y, direction.x, direction.z)
+            } else {
+                (CubeFace::NegativeY, abs.y, direction.x, -direction.z)
+            }
+        } else if direction.z > 0.0 {
+            (CubeFace::PositiveZ, abs.z, direction.x, -direction.y)
+        } else {
+            (CubeFace::NegativeZ, abs.z, -direction.x, -direction.y)
+        };
+        let major = major.max(f32::MIN_POSITIVE);
+        (face, (Vec2::new(s, t) / major + 1.0) * 0.5)
+    }
+
+    /// Returns the direction through the texture coordinate of the face, the
+    /// inverse of [`CubeFace::from_direction`]. Not normalized.
+    pub fn direction(self, tex_coord: Vec2) -> Vec3 {
+        let s = tex_coord.x * 2.0 - 1.0;
+        let t = tex_coord.y * 2.0 - 1.0;
+        match self {
+            CubeFace::PositiveX => Vec3::new(1.0, -t, -s),
+            CubeFace::NegativeX => Vec3::new(-1.0, -t, s),
+            CubeFace::PositiveY => Vec3::new(s, 1.0, t),
+            CubeFace::NegativeY => Vec3::new(s, -1.0, -t),
+            CubeFace::PositiveZ => Vec3::new(s, -t, 1.0),
+            CubeFace::NegativeZ => Vec3::new(-s, -t, -1.0),
+        }
+    }
+}
+
+/// A texture with six square faces that is sampled by direction. Values are
+/// linear colors and may be HDR.
+#[derive(Clone)]
+pub struct Cubemap {
+    size: u32,
+    /// Texels of the faces in the order of [`CubeFace::ALL`]
+    faces: [Vec<Vec3>; 6],
+}
+
+impl Cubemap {
+    pub fn new(size: u32, faces: [Vec<Vec3>; 6]) -> Self {
+        for face in &faces {
+            assert_eq!(face.len(), (size * size) as usize);
+        }
+        Self { size, faces }
+    }
+
+    /// Creates a cubemap from a function of the direction of every texel
+    pub fn from_fn(size: u32, f: impl Fn(Vec3) -> Vec3) -> Self {
+        let faces = CubeFace::ALL.map(|face| {
+            let mut data = Vec::with_capacity((size * size) as usize);
+            for y in 0..size {
+                for x in 0..size {
+                    let tex_coord =
+                        (Vec2::new(x as f32, y as f32) + 0.5) / size as f32;
+                    data.push(f(face.direction(tex_coord).normalize()));
+                }
+            }
+            data
+        });
+        Self::new(size, faces)
+    }
+
+    /// Loads the faces from six square images of the same size, given in the
+    /// order of [`CubeFace::ALL`]. Images that aren't floating point are
+    /// decoded from sRGB.
+    pub fn from_files(file_paths: [&str; 6]) -> Result<Self> {
+        let mut size = None;
+        let mut faces: [Vec<Vec3>; 6] = Default::default();
+        for (face, file_path) in faces.iter_mut().zip(file_paths) {
+            let (width, height, data) = load_linear_image(file_path)?;
+            if width != height || size.is_some_and(|size| size != width) {
+                anyhow::bail!(
+                    "Cubemap faces have to be square and of the same size, \
+                     {} is {}x{}",
+                    file_path,
+                    width,
+                    height
+                );
+            }
+            size = Some(width);
+            *face = data;
+        }
+        Ok(Self::new(size.unwrap(), faces))
+    }
+
+    /// Resamples an equirectangular map into faces of the given size
+    pub fn from_equirect(map: &EquirectMap, size: u32) -> Self {
+        Self::from_fn(size, |direction| map.sample(direction))
+    }
+
+    /// Resamples the cubemap into an equirectangular map of the given width,
+    /// for example to compute image based lighting from it
+    pub fn to_equirect(&self, width: u32) -> EquirectMap {
+        EquirectMap::from_fn(width, width / 2, |direction| {
+            self.sample(direction)
+        })
+    }
+
+    /// Returns the width and height of the faces
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn texel(&self, face: CubeFace, x: u32, y: u32) -> Vec3 {
+        self.faces[face as usize][(x + y * self.size) as usize]
+    }
+
+    /// Returns the bilinearly filtered value in the given direction. Texels
+    /// are clamped at the edges of a face.
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        let (face, tex_coord) = CubeFace::from_direction(direction);
+        let max = (self.size - 1) as f32;
+        let x = (tex_coord.x * self.size as f32 - 0.5).clamp(0.0, max);
+        let y = (tex_coord.y * self.size as f32 - 0.5).clamp(0.0, max);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) =
+            ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+        let (tx, ty) = (x.fract(), y.fract());
+
+        let top = self.texel(face, x0, y0).lerp(self.texel(face, x1, y0), tx);
+        let bottom =
+            self.texel(face, x0, y1).lerp(self.texel(face, x1, y1), tx);
+        top.lerp(bottom, ty)
+    }
+}
+
+/// Draws a cubemap behind everything else. The skybox is drawn as seen from
+/// the camera and fills the whole framebuffer, so it replaces the clear
+/// color.
+pub struct Skybox {
+    cubemap: Cubemap,
+}
+
+impl Skybox {
+    pub fn new(cubemap: Cubemap) -> Self {
+        Self { cubemap }
+    }
+
+    pub fn cubemap(&self) -> &Cubemap {
+        &self.cubemap
+    }
+
+    /// Fills the color buffer with the skybox. Has to be called after the
+    /// rasterizer was cleared and before the scene is drawn. Depth and
+    /// stencil are not touched.
+    pub fn draw(&self, rasterizer: &mut Rasterizer, camera: &Camera) {
+        let width = rasterizer.framebuffer().width();
+        let height = rasterizer.framebuffer().height();
+        let size = Vec2::new(width as f32, height as f32);
+        let inv_view_proj = camera.view_proj_mat().inverse();
+        let (near_z, far_z) = camera.ndc_depth_range();
+
+        for y in 0..height {
+            for x in 0..width {
+                let ndc =
+                    (Vec2::new(x as f32, y as f32) + 0.5) / size * 2.0 - 1.0;
+                let near = inv_view_proj.project_point3(ndc.extend(near_z));
+                let far = inv_view_proj.project_point3(ndc.extend(far_z));
+                let color = self.cubemap.sample(far - near);
+                rasterizer.set_pixel_linear((x, y), color);
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use crate::prelude::*;
+
+/// Width and height in pixels of the screen tiles lights are culled for
+pub const LIGHT_TILE_SIZE: u32 = 16;
+
+/// Color attachments of the G-buffer. The normal attachment stores the
+/// reflectivity in its fourth component.
+const ALBEDO_ATTACHMENT: usize = 0;
+const NORMAL_ATTACHMENT: usize = 1;
+const DEPTH_ATTACHMENT: usize = 2;
+
+/// Renders meshes in two passes. The geometry pass writes the albedo, normal
+/// and view depth of the closest surfaces into color attachments, the
+/// lighting pass then shades every covered pixel once. Lights are culled
+/// against tiles of [`LIGHT_TILE_SIZE`] pixels, so a pixel only evaluates
+/// the lights that can reach it.
+///
+/// The shading is the same as the one of the [`LitShader`]. With SSAO the
+/// ambient light is occluded using the depth and the G-buffer normals.
+#[derive(Default)]
+pub struct DeferredRenderer<'a> {
+    /// Indices of the lights that reach the current tile
+    tile_lights: Vec<usize>,
+    ssao: Option<Ssao>,
+    /// Cubemap that is reflected
+    environment: Option<&'a Cubemap>,
+    stats: DeferredStats,
+}
+
+/// Counts the work of the last lighting pass
+#[derive(Default, Copy, Clone, Debug)]
+pub struct DeferredStats {
+    /// Tiles that contain at least one covered pixel
+    pub tiles_lit: u32,
+    /// Sum of the lights of all lit tiles
+    pub tile_lights: u32,
+}
+
+/// Screen space rectangle and view depth of the volume a light reaches
+struct LightBounds {
+    min: Vec2,
+    max: Vec2,
+    depth: f32,
+    range: f32,
+}
+
+impl<'a> DeferredRenderer<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Occludes the ambient light with screen space ambient occlusion
+    pub fn with_ssao(mut self, ssao: Ssao) -> Self {
+        self.ssao = Some(ssao);
+        self
+    }
+
+    /// Reflects the cubemap by the reflectivity of the materials
+    pub fn with_environment(mut self, cubemap: &'a Cubemap) -> Self {
+        self.environment = Some(cubemap);
+        self
+    }
+
+    /// Starts a frame by replacing the color attachments of the framebuffer
+    /// with the G-buffer. Has to be called after the rasterizer was cleared.
+    pub fn begin(&mut self, rasterizer: &mut Rasterizer) {
+        rasterizer.remove_color_attachments();
+        rasterizer.add_color_attachment(ColorFormat::Rgba16F, Vec4::ZERO);
+        rasterizer.add_color_attachment(ColorFormat::Rgba16F, Vec4::ZERO);
+        rasterizer.add_color_attachment(ColorFormat::R32F, Vec4::ZERO);
+    }
+
+    /// Writes the mesh into the G-buffer
+    pub fn draw_mesh(
+        &self,
+        rasterizer: &mut Rasterizer,
+        mesh: &Mesh,
+        camera: &Camera,
+    ) {
+        let shader = GBufferShader::new(
+            camera.view_proj_mat(),
+            camera.view_mat(),
+            mesh.material(),
+        );
+        rasterizer.draw_mesh(mesh, &shader);
+    }
+
+    /// Lights every pixel that is covered by a mesh and removes the G-buffer
+    /// from the framebuffer
+    pub fn end(
+        &mut self,
+        rasterizer: &mut Rasterizer,
+        camera: &Camera,
+        lights: &[Light],
+        ambient: Vec3,
+    ) {
+        let width = rasterizer.framebuffer().width();
+        let height = rasterizer.framebuffer().height();
+        let size = Vec2::new(width as f32, height as f32);
+        let view = camera.view_mat();
+        let proj = camera.proj_mat();
+        let inv_view = view.inverse();
+        let inv_proj = proj.inverse();
+        let depth_range = camera.ndc_depth_range();
+        let camera_position = camera.position();
+
+        let ao_map = self.ssao.as_ref().map(|ssao| {
+            ssao.compute(rasterizer, camera, Some(NORMAL_ATTACHMENT))
+        });
+
+        let bounds: Vec<_> = lights
+            .iter()
+            .map(|light| light_bounds(light, view, proj * view, size))
+            .collect();
+
+        self.stats = DeferredStats::default();
+        for tile_y in 0..height.div_ceil(LIGHT_TILE_SIZE) {
+            for tile_x in 0..width.div_ceil(LIGHT_TILE_SIZE) {
+                let x_range = tile_x * LIGHT_TILE_SIZE
+                    ..((tile_x + 1) * LIGHT_TILE_SIZE).min(width);
+                let y_range = tile_y * LIGHT_TILE_SIZE
+                    ..((tile_y + 1) * LIGHT_TILE_SIZE).min(height);
+
+                // Depth range of the covered pixels in the tile
+                let mut depth_min = f32::MAX;
+                let mut depth_max = f32::MIN;
+                for y in y_range.clone() {
+                    for x in x_range.clone() {
+                        let albedo =
+                            rasterizer.attachment(ALBEDO_ATTACHMENT, (x, y));
+                        if albedo.w > 0.0 {
+                            let depth = rasterizer
+                                .attachment(DEPTH_ATTACHMENT, (x, y))
+                                .x;
+                            depth_min = depth_min.min(depth);
+                            depth_max = depth_max.max(depth);
+                        }
+                    }
+                }
+                if depth_min > depth_max {
+                    continue;
+                }
+
+                // Keep the lights whose volume overlaps the tile
+                let tile_min =
+                    Vec2::new(x_range.start as f32, y_range.start as f32);
+                let tile_max =
+                    Vec2::new(x_range.end as f32, y_range.end as f32);
+                self.tile_lights.clear();
+                for (idx, light) in bounds.iter().enumerate() {
+                    if light.min.cmple(tile_max).all()
+                        && light.max.cmpge(tile_min).all()
+                        && light.depth - light.range <= depth_max
+                        && light.depth + light.range >= depth_min
+                    {
+                        self.tile_lights.push(idx);
+                    }
+                }
+                self.stats.tiles_lit += 1;
+                self.stats.tile_lights += self.tile_lights.len() as u32;
+
+                for y in y_range.clone() {
+                    for x in x_range.clone() {
+                        let albedo =
+                            rasterizer.attachment(ALBEDO_ATTACHMENT, (x, y));
+                        if albedo.w <= 0.0 {
+                            continue;
+                        }
+                        let normal_reflectivity =
+                            rasterizer.attachment(NORMAL_ATTACHMENT, (x, y));
+                        let normal =
+                            normal_reflectivity.truncate().normalize_or_zero();
+                        let depth =
+                            rasterizer.attachment(DEPTH_ATTACHMENT, (x, y)).x;
+
+                        let ndc =
+                            Vec2::new(x as f32, y as f32) / size * 2.0 - 1.0;
+                        let position = inv_view.transform_point3(
+                            view_position(inv_proj, depth_range, ndc, depth),
+                        );
+                        let occlusion = ao_map
+                            .as_ref()
+                            .map_or(1.0, |ao_map| ao_map.get((x, y)));
+                        let mut color = shade_lambert(
+                            albedo.truncate(),
+                            position,
+                            normal,
+                            ambient * occlusion,
+                            self.tile_lights.iter().map(|&idx| &lights[idx]),
+                        );
+                        let reflectivity = normal_reflectivity.w;
+                        if let Some(cubemap) = self.environment {
+                            if reflectivity > 0.0 {
+                                let incident = position - camera_position;
+                                let reflection = incident
+                                    - 2.0 * incident.dot(normal) * normal;
+                                color = color.lerp(
+                                    cubemap.sample(reflection),
+                                    reflectivity,
+                                );
+                            }
+                        }
+                        let color = rasterizer.apply_fog(color, position);
+                        rasterizer.set_pixel_linear((x, y), color);
+                    }
+                }
+            }
+        }
+
+        rasterizer.remove_color_attachments();
+    }
+
+    /// Returns the statistics of the last lighting pass
+    pub fn stats(&self) -> DeferredStats {
+        self.stats
+    }
+}
+
+/// Returns the screen space rectangle that contains the sphere the light
+/// reaches. If the sphere reaches behind the camera or the light is
+/// directional, the whole screen is covered.
+fn light_bounds(
+    light: &Light,
+    view: Mat4,
+    view_proj: Mat4,
+    size: Vec2,
+) -> LightBounds {
+    let everywhere = LightBounds {
+        min: Vec2::splat(f32::MIN),
+        max: Vec2::splat(f32::MAX),
+        depth: 0.0,
+        range: f32::INFINITY,
+    };
+    let Some(position) = light.position() else {
+        return everywhere;
+    };
+    if light.range.is_infinite() {
+        return everywhere;
+    }
+
+    let depth = -view.transform_point3(position).z;
+    let mut min = Vec2::splat(f32::MAX);
+    let mut max = Vec2::splat(f32::MIN);
+    for corner in 0..8 {
+        let offset = Vec3::new(
+            if corner & 1 == 0 { -1.0 } else { 1.0 },
+            if corner & 2 == 0 { -1.0 } else { 1.0 },
+            if corner & 4 == 0 { -1.0 } else { 1.0 },
+        ) * light.range;
+        let clip = view_proj * (position + offset).extend(1.0);
+        if clip.w <= 0.0 {
+            return LightBounds {
+                depth,
+                range: light.range,
+                ..everywhere
+            };
+        }
+        let screen = (clip.truncate().truncate() / clip.w + 1.0) * 0.5 * size;
+        min = min.min(screen);
+        max = max.max(screen);
+    }
+    LightBounds {
+        min,
+        max,
+        depth,
+        range: light.range,
+    }
+}
+
+/// Reconstructs the view space position of a pixel from its normalized
+/// device coordinates and view depth. Works for every projection by
+/// intersecting the ray through the pixel with the plane at the depth.
+/// `depth_range` is the depth of the near and far plane in normalized device
+/// coordinates.
+fn view_position(
+    inv_proj: Mat4,
+    depth_range: (f32, f32),
+    ndc: Vec2,
+    depth: f32,
+) -> Vec3 {
+    let near = inv_proj.project_point3(ndc.extend(depth_range.0));
+    let far = inv_proj.project_point3(ndc.extend(depth_range.1));
+    let t = (-depth - near.z) / (far.z - near.z);
+    near.lerp(far, t)
+}
+
+/// Writes the albedo, the shading normal with the reflectivity and the view
+/// depth of a mesh into the G-buffer. The color is the albedo.
+pub struct GBufferShader<'a> {
+    transform: Mat4,
+    view: Mat4,
+    material: &'a Material,
+}
+
+impl<'a> GBufferShader<'a> {
+    pub fn new(transform: Mat4, view: Mat4, material: &'a Material) -> Self {
+        Self {
+            transform,
+            view,
+            material,
+        }
+    }
+}
+
+impl<'a> Shader for GBufferShader<'a> {
+    fn vertex(&self, vertex: &Vertex) -> VertexOutput {
+        VertexOutput {
+            position: self.transform * vertex.position.extend(1.0),
+            varyings: Varyings::from_vertex(vertex),
+        }
+    }
+
+    fn fragment(&self, varyings: &Varyings) -> Option<Color> {
+        if !alpha_test(self.material, varyings.tex_coord) {
+            return None;
+        }
+        Some(diffuse_color(self.material, varyings.tex_coord))
+    }
+
+    fn fragment_outputs(
+        &self,
+        varyings: &Varyings,
+        outputs: &mut [Vec4],
+    ) -> Option<Vec3> {
+        if !alpha_test(self.material, varyings.tex_coord) {
+            return None;
+        }
+        let albedo = albedo(self.material, varyings.tex_coord);
+        let normal = shading_normal(self.material, varyings);
+        let depth = -self.view.transform_point3(varyings.position).z;
+        outputs[ALBEDO_ATTACHMENT] = albedo.extend(1.0);
+        outputs[NORMAL_ATTACHMENT] =
+            normal.extend(self.material.reflectivity(varyings.tex_coord));
+        outputs[DEPTH_ATTACHMENT] = Vec4::new(depth, 0.0, 0.0, 0.0);
+        Some(albedo)
+    }
+}
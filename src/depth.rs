@@ -0,0 +1,120 @@
+/// Function that compares an incoming value against a stored one
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompareFunc {
+    Never,
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+impl CompareFunc {
+    /// Returns true if `value` passes the comparison against `stored`
+    pub fn test<T: PartialOrd>(self, value: T, stored: T) -> bool {
+        match self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => value < stored,
+            CompareFunc::Equal => value == stored,
+            CompareFunc::LessEqual => value <= stored,
+            CompareFunc::Greater => value > stored,
+            CompareFunc::NotEqual => value != stored,
+            CompareFunc::GreaterEqual => value >= stored,
+            CompareFunc::Always => true,
+        }
+    }
+
+    /// Returns whether larger values win the comparison. `None` for
+    /// functions that don't prefer a direction.
+    pub fn greater_wins(self) -> Option<bool> {
+        match self {
+            CompareFunc::Less | CompareFunc::LessEqual => Some(false),
+            CompareFunc::Greater | CompareFunc::GreaterEqual => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// How fragments are tested against and written to the depth buffer
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DepthState {
+    pub compare: CompareFunc,
+    /// Write the depth of fragments that pass the test
+    pub write: bool,
+    /// Map the near plane to a depth of 1 and the far plane to 0 instead of
+    /// the other way around. Needs a `Greater` or `GreaterEqual` compare
+    /// function, a depth buffer that is cleared to 0 and a camera with
+    /// reversed Z, whose clip space depth is stored as it is.
+    pub reversed_z: bool,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        Self {
+            compare: CompareFunc::Less,
+            write: true,
+            reversed_z: false,
+        }
+    }
+}
+
+impl DepthState {
+    /// Depth state for a reversed depth range with the matching compare
+    /// function
+    pub fn reversed() -> Self {
+        Self {
+            compare: CompareFunc::Greater,
+            write: true,
+            reversed_z: true,
+        }
+    }
+
+    /// Returns the value the depth buffer has to be cleared to, so that
+    /// everything passes the depth test
+    pub fn clear_value(&self) -> f32 {
+        if self.reversed_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Storage format of a depth buffer. The normalized formats clamp depth to
+/// [0, 1] and quantize it like a GPU would.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepthFormat {
+    /// 16 bit unsigned normalized
+    D16,
+    /// 24 bit unsigned normalized
+    D24,
+    /// 32 bit float
+    F32,
+}
+
+impl DepthFormat {
+    /// Rounds the depth to the closest value the format can store
+    pub fn quantize(self, depth: f32) -> f32 {
+        match self {
+            DepthFormat::D16 => unorm_to_f32(f32_to_unorm(depth, 16), 16),
+            DepthFormat::D24 => unorm_to_f32(f32_to_unorm(depth, 24), 24),
+            DepthFormat::F32 => depth,
+        }
+    }
+}
+
+/// Converts a depth to an unsigned normalized integer with the given number
+/// of bits
+pub(crate) fn f32_to_unorm(depth: f32, bits: u32) -> u32 {
+    let max = ((1u64 << bits) - 1) as f64;
+    (depth.clamp(0.0, 1.0) as f64 * max).round() as u32
+}
+
+/// Converts an unsigned normalized integer with the given number of bits
+/// back to a depth
+pub(crate) fn unorm_to_f32(value: u32, bits: u32) -> f32 {
+    let max = ((1u64 << bits) - 1) as f64;
+    (value as f64 / max) as f32
+}
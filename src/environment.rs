@@ -0,0 +1,400 @@
+use crate::prelude::*;
+
+use std::f32::consts::{PI, TAU};
+
+/// Number of prefiltered specular levels, from roughness 0 to 1
+pub const SPECULAR_LEVELS: usize = 6;
+/// Width of the first prefiltered specular level, the height is half of it
+const SPECULAR_SIZE: u32 = 256;
+/// Width of the diffuse irradiance map, the height is half of it
+const IRRADIANCE_SIZE: u32 = 32;
+/// Width of the radiance map the irradiance is integrated from
+const IRRADIANCE_SOURCE_SIZE: u32 = 64;
+/// Width and height of the split sum BRDF lookup table
+const BRDF_LUT_SIZE: u32 = 32;
+/// GGX samples per texel of the prefiltered specular levels
+const SPECULAR_SAMPLES: u32 = 64;
+/// Samples per entry of the BRDF lookup table
+const BRDF_SAMPLES: u32 = 256;
+
+/// An image that covers all directions around a point in the
+/// equirectangular (latitude-longitude) layout. The center of the image looks
+/// along -Z and the top row along +Y. Values are linear HDR colors.
+#[derive(Clone)]
+pub struct EquirectMap {
+    width: u32,
+    height: u32,
+    data: Vec<Vec3>,
+}
+
+impl EquirectMap {
+    pub fn new(width: u32, height: u32, data: Vec<Vec3>) -> Self {
+        assert_eq!(data.len(), (width * height) as usize);
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Creates a map from a function of the direction of every texel
+    pub fn from_fn(width: u32, height: u32, f: impl Fn(Vec3) -> Vec3) -> Self {
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(f(texel_direction(x, y, width, height)));
+            }
+        }
+        Self::new(width, height, data)
+    }
+
+    /// Loads an equirectangular image, usually a Radiance `.hdr` file
+    pub fn from_file(file_path: &str) -> Result<Self> {
+        let (width, height, data) = load_linear_image(file_path)?;
+        Ok(Self::new(width, height, data))
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn texel(&self, x: u32, y: u32) -> Vec3 {
+        self.data[(x + y * self.width) as usize]
+    }
+
+    /// Returns the bilinearly filtered value in the given direction
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        let direction = direction.normalize_or_zero();
+        let u = direction.x.atan2(-direction.z) / TAU + 0.5;
+        let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+
+        let x = u * self.width as f32 - 0.5;
+        let y =
+            (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        // Wrap around horizontally, clamp at the poles
+        let wrap = |x: f32| x.rem_euclid(self.width as f32) as u32;
+        let x1 = wrap(x0 + 1.0);
+        let x0 = wrap(x0);
+        let y1 = (y0 as u32 + 1).min(self.height - 1);
+        let y0 = y0 as u32;
+
+        let top = self.texel(x0, y0).lerp(self.texel(x1, y0), tx);
+        let bottom = self.texel(x0, y1).lerp(self.texel(x1, y1), tx);
+        top.lerp(bottom, ty)
+    }
+
+    /// Returns a map with half the width and height by averaging 2x2 texels
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut data = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Vec3::ZERO;
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (2 * x + dx).min(self.width - 1);
+                    let sy = (2 * y + dy).min(self.height - 1);
+                    sum += self.texel(sx, sy);
+                }
+                data.push(sum * 0.25);
+            }
+        }
+        Self::new(width, height, data)
+    }
+}
+
+/// Lighting from an environment map. The diffuse irradiance and the
+/// specular reflections for different roughnesses are precomputed, so
+/// shading only needs a few lookups.
+pub struct Environment {
+    radiance: EquirectMap,
+    irradiance: EquirectMap,
+    /// Radiance convolved with the GGX distribution for increasing roughness
+    specular_levels: Vec<EquirectMap>,
+    /// Scale and bias of the specular reflectance by normal-view angle and
+    /// roughness
+    brdf_lut: Vec<Vec2>,
+}
+
+impl Environment {
+    /// Precomputes the lighting of the given radiance map
+    pub fn new(radiance: EquirectMap) -> Self {
+        // Downsampled versions of the radiance, used to sample large solid
+        // angles without noise
+        let mut mips = vec![radiance.clone()];
+        while mips.last().unwrap().width > SPECULAR_SIZE {
+            let mip = mips.last().unwrap().downsample();
+            mips.push(mip);
+        }
+        let first_level = mips.len() - 1;
+        while mips.last().unwrap().width > 1 {
+            let mip = mips.last().unwrap().downsample();
+            mips.push(mip);
+        }
+
+        let irradiance_source = mips
+            .iter()
+            .find(|mip| mip.width <= IRRADIANCE_SOURCE_SIZE)
+            .unwrap();
+        let irradiance = compute_irradiance(irradiance_source);
+
+        let mut specular_levels = vec![mips[first_level].clone()];
+        for level in 1..SPECULAR_LEVELS {
+            let roughness = level as f32 / (SPECULAR_LEVELS - 1) as f32;
+            let width = (SPECULAR_SIZE >> level).max(8);
+            specular_levels.push(prefilter_specular(
+                &mips[first_level..],
+                width,
+                roughness,
+            ));
+        }
+
+        Self {
+            radiance,
+            irradiance,
+            specular_levels,
+            brdf_lut: compute_brdf_lut(),
+        }
+    }
+
+    /// Loads an equirectangular HDR image and precomputes its lighting
+    pub fn from_file(file_path: &str) -> Result<Self> {
+        Ok(Self::new(EquirectMap::from_file(file_path)?))
+    }
+
+    /// Returns the unfiltered radiance map
+    pub fn radiance(&self) -> &EquirectMap {
+        &self.radiance
+    }
+
+    /// Returns the cosine weighted average radiance around the normal. A
+    /// white diffuse surface with this normal reflects exactly this.
+    pub fn irradiance(&self, normal: Vec3) -> Vec3 {
+        self.irradiance.sample(normal)
+    }
+
+    /// Returns the radiance around the reflection direction, blurred for
+    /// the given perceptual roughness
+    pub fn specular(&self, direction: Vec3, roughness: f32) -> Vec3 {
+        let level =
+            roughness.clamp(0.0, 1.0) * (self.specular_levels.len() - 1) as f32;
+        let lower = level.floor() as usize;
+        let upper = (lower + 1).min(self.specular_levels.len() - 1);
+        let a = self.specular_levels[lower].sample(direction);
+        let b = self.specular_levels[upper].sample(direction);
+        a.lerp(b, level - lower as f32)
+    }
+
+    /// Returns the scale and bias that are applied to the specular
+    /// reflectance at normal incidence by the split sum approximation
+    pub fn brdf(&self, n_dot_v: f32, roughness: f32) -> Vec2 {
+        let max = (BRDF_LUT_SIZE - 1) as f32;
+        let x = (n_dot_v.clamp(0.0, 1.0) * max).round() as u32;
+        let y = (roughness.clamp(0.0, 1.0) * max).round() as u32;
+        self.brdf_lut[(x + y * BRDF_LUT_SIZE) as usize]
+    }
+}
+
+/// Returns a simple sky with a sun, used when no environment map is given
+pub fn procedural_sky() -> EquirectMap {
+    let sun = sun_direction();
+    EquirectMap::from_fn(512, 256, |direction| {
+        let sky = if direction.y >= 0.0 {
+            let t = direction.y.sqrt();
+            Vec3::new(0.9, 0.95, 1.0).lerp(Vec3::new(0.25, 0.45, 0.9), t)
+        } else {
+            let t = (-direction.y * 8.0).min(1.0);
+            Vec3::new(0.6, 0.6, 0.6).lerp(Vec3::new(0.2, 0.17, 0.14), t)
+        };
+        let sun_disc = if direction.dot(sun) > 0.998 {
+            400.0
+        } else {
+            0.0
+        };
+        sky + Vec3::splat(sun_disc)
+    })
+}
+
+/// Direction towards the sun of the [`procedural_sky`]
+pub fn sun_direction() -> Vec3 {
+    Vec3::new(0.5, 0.7, 0.5).normalize()
+}
+
+/// Loads an image as linear colors and returns its width, height and texels.
+/// Floating point images are linear already, all other formats are decoded
+/// from sRGB.
+pub(crate) fn load_linear_image(
+    file_path: &str,
+) -> Result<(u32, u32, Vec<Vec3>)> {
+    let image = image::open(file_path)?;
+    let is_float = matches!(
+        image,
+        image::DynamicImage::ImageRgb32F(_)
+            | image::DynamicImage::ImageRgba32F(_)
+    );
+    let image = image.to_rgb32f();
+    let data = image
+        .pixels()
+        .map(|p| {
+            let color = Vec3::from_array(p.0);
+            if is_float {
+                color
+            } else {
+                srgb_to_linear(color)
+            }
+        })
+        .collect();
+    Ok((image.width(), image.height(), data))
+}
+
+/// Returns the direction through the center of a texel
+fn texel_direction(x: u32, y: u32, width: u32, height: u32) -> Vec3 {
+    let phi = ((x as f32 + 0.5) / width as f32 - 0.5) * TAU;
+    let theta = (y as f32 + 0.5) / height as f32 * PI;
+    Vec3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+/// Integrates the cosine weighted radiance over the hemisphere of every
+/// texel by brute force
+fn compute_irradiance(radiance: &EquirectMap) -> EquirectMap {
+    // Direction, radiance and solid angle of every source texel
+    let (width, height) = (radiance.width, radiance.height);
+    let mut texels = Vec::with_capacity(radiance.data.len());
+    for y in 0..height {
+        let theta = (y as f32 + 0.5) / height as f32 * PI;
+        let solid_angle = TAU / width as f32 * PI / height as f32 * theta.sin();
+        for x in 0..width {
+            let direction = texel_direction(x, y, width, height);
+            texels.push((direction, radiance.texel(x, y) * solid_angle));
+        }
+    }
+
+    EquirectMap::from_fn(IRRADIANCE_SIZE, IRRADIANCE_SIZE / 2, |normal| {
+        let mut irradiance = Vec3::ZERO;
+        for &(direction, value) in &texels {
+            irradiance += value * normal.dot(direction).max(0.0);
+        }
+        irradiance / PI
+    })
+}
+
+/// Convolves the radiance with the GGX distribution of the given roughness,
+/// assuming that the view direction equals the normal. Samples are taken from
+/// the mip level that matches their solid angle.
+fn prefilter_specular(
+    mips: &[EquirectMap],
+    width: u32,
+    roughness: f32,
+) -> EquirectMap {
+    let alpha = roughness * roughness;
+    let texel_solid_angle = 4.0 * PI / (mips[0].width * mips[0].height) as f32;
+
+    EquirectMap::from_fn(width, width / 2, |normal| {
+        let mut sum = Vec3::ZERO;
+        let mut weight = 0.0;
+        for i in 0..SPECULAR_SAMPLES {
+            let xi = hammersley(i, SPECULAR_SAMPLES);
+            let half = importance_sample_ggx(xi, normal, alpha);
+            let light = 2.0 * normal.dot(half) * half - normal;
+            let n_dot_l = normal.dot(light);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+
+            // With the view along the normal the pdf is D / 4
+            let n_dot_h = normal.dot(half).max(0.0);
+            let pdf = distribution_ggx(n_dot_h, alpha) / 4.0;
+            let sample_solid_angle =
+                1.0 / (SPECULAR_SAMPLES as f32 * pdf + 1e-4);
+            let lod = 0.5 * (sample_solid_angle / texel_solid_angle).log2();
+            let mip = (lod.max(0.0).round() as usize).min(mips.len() - 1);
+
+            sum += mips[mip].sample(light) * n_dot_l;
+            weight += n_dot_l;
+        }
+        if weight > 0.0 {
+            sum / weight
+        } else {
+            mips[0].sample(normal)
+        }
+    })
+}
+
+/// Precomputes the scale and bias of the specular reflectance for the split
+/// sum approximation, indexed by the cosine between normal and view and the
+/// roughness
+fn compute_brdf_lut() -> Vec<Vec2> {
+    let mut lut = Vec::with_capacity((BRDF_LUT_SIZE * BRDF_LUT_SIZE) as usize);
+    for y in 0..BRDF_LUT_SIZE {
+        let roughness = y as f32 / (BRDF_LUT_SIZE - 1) as f32;
+        let alpha = roughness * roughness;
+        for x in 0..BRDF_LUT_SIZE {
+            let n_dot_v = (x as f32 / (BRDF_LUT_SIZE - 1) as f32).max(1e-3);
+            let view =
+                Vec3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+
+            let mut scale = 0.0;
+            let mut bias = 0.0;
+            for i in 0..BRDF_SAMPLES {
+                let xi = hammersley(i, BRDF_SAMPLES);
+                let half = importance_sample_ggx(xi, Vec3::Z, alpha);
+                let light = 2.0 * view.dot(half) * half - view;
+                let n_dot_l = light.z;
+                if n_dot_l <= 0.0 {
+                    continue;
+                }
+                let n_dot_h = half.z.max(0.0);
+                let v_dot_h = view.dot(half).max(0.0);
+
+                let k = alpha / 2.0;
+                let g =
+                    geometry_schlick(n_dot_v, k) * geometry_schlick(n_dot_l, k);
+                let g_vis = g * v_dot_h / (n_dot_h * n_dot_v).max(1e-6);
+                let fresnel = (1.0 - v_dot_h).powi(5);
+                scale += (1.0 - fresnel) * g_vis;
+                bias += fresnel * g_vis;
+            }
+            lut.push(Vec2::new(scale, bias) / BRDF_SAMPLES as f32);
+        }
+    }
+    lut
+}
+
+/// The i-th point of a low discrepancy sequence of `count` points in
+/// [0, 1)^2
+fn hammersley(i: u32, count: u32) -> Vec2 {
+    let radical_inverse = i.reverse_bits() as f32 / 4_294_967_296.0;
+    Vec2::new(i as f32 / count as f32, radical_inverse)
+}
+
+/// Returns a half vector around the normal that is distributed like the GGX
+/// distribution with the given alpha
+fn importance_sample_ggx(xi: Vec2, normal: Vec3, alpha: f32) -> Vec3 {
+    let phi = TAU * xi.x;
+    let cos_theta =
+        ((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let half =
+        Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, cos_theta);
+
+    // From tangent space around the normal to world space
+    let up = if normal.z.abs() < 0.999 {
+        Vec3::Z
+    } else {
+        Vec3::X
+    };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    (tangent * half.x + bitangent * half.y + normal * half.z).normalize()
+}
@@ -0,0 +1,117 @@
+use crate::prelude::*;
+
+/// How the fog thickens with the view depth
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FogMode {
+    /// No fog before `start`, full fog after `end`
+    Linear,
+    /// Visibility falls off with `exp(-density * depth)`
+    Exp,
+    /// Visibility falls off with `exp(-(density * depth)^2)`, which keeps
+    /// the near range clearer
+    Exp2,
+}
+
+/// Fog that gets thicker towards the ground. The density falls off
+/// exponentially above `base_height`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeightFog {
+    /// Density at the base height
+    pub density: f32,
+    /// World space height at which the density is `density`
+    pub base_height: f32,
+    /// How fast the density falls off with the height, per unit
+    pub falloff: f32,
+}
+
+/// Blends fragments towards a color with their view space depth
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Fog {
+    pub mode: FogMode,
+    /// Linear color of the fog
+    pub color: Vec3,
+    /// Density of the exponential modes
+    pub density: f32,
+    /// View depth range of the linear mode
+    pub start: f32,
+    pub end: f32,
+    /// Additional fog that depends on the height
+    pub height_fog: Option<HeightFog>,
+}
+
+impl Fog {
+    /// Creates a fog without height fog. `start` and `end` are only used by
+    /// the linear mode, `density` only by the exponential ones.
+    pub fn new(mode: FogMode, color: Vec3, density: f32) -> Self {
+        Self {
+            mode,
+            color,
+            density,
+            start: 0.0,
+            end: 1.0 / density.max(1e-6),
+            height_fog: None,
+        }
+    }
+
+    pub fn with_range(mut self, start: f32, end: f32) -> Self {
+        self.start = start;
+        self.end = end;
+        self
+    }
+
+    pub fn with_height_fog(mut self, height_fog: HeightFog) -> Self {
+        self.height_fog = Some(height_fog);
+        self
+    }
+
+    /// Returns the fraction of the surface color that remains visible. `depth`
+    /// is the view space depth, `camera_position` and `position` are in world
+    /// space and only used by the height fog.
+    pub fn visibility(
+        &self,
+        depth: f32,
+        camera_position: Vec3,
+        position: Vec3,
+    ) -> f32 {
+        let depth = depth.max(0.0);
+        let mut visibility = match self.mode {
+            FogMode::Linear => {
+                let range = (self.end - self.start).max(1e-6);
+                ((self.end - depth) / range).clamp(0.0, 1.0)
+            }
+            FogMode::Exp => (-self.density * depth).exp(),
+            FogMode::Exp2 => (-(self.density * depth).powi(2)).exp(),
+        };
+
+        if let Some(height_fog) = self.height_fog {
+            // Integral of the density along the ray from the camera
+            let distance = camera_position.distance(position);
+            let falloff = height_fog.falloff;
+            let camera_height = camera_position.y - height_fog.base_height;
+            let rise = (position.y - camera_position.y) * falloff;
+            let along_ray = if rise.abs() > 1e-4 {
+                (1.0 - (-rise).exp()) / rise
+            } else {
+                1.0
+            };
+            let amount = height_fog.density
+                * distance
+                * (-falloff * camera_height).exp()
+                * along_ray;
+            visibility *= (-amount).exp();
+        }
+        visibility
+    }
+
+    /// Blends a linear color towards the fog color
+    pub fn apply(
+        &self,
+        color: Vec3,
+        depth: f32,
+        camera_position: Vec3,
+        position: Vec3,
+    ) -> Vec3 {
+        let visibility = self.visibility(depth, camera_position, position);
+        self.color.lerp(color, visibility)
+    }
+}
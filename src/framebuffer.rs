@@ -1,21 +1,96 @@
 use crate::prelude::*;
 
-/// A framebuffer holds a color and depth buffer
+/// A framebuffer holds a color, depth and 8 bit stencil buffer. Additional
+/// color attachments with their own formats can be added.
+///
+/// With an HDR target, colors are drawn as linear floats and only written
+/// to the color buffer by [`Framebuffer::resolve_hdr`].
 pub struct Framebuffer {
     color_buffer: Vec<u32>,
-    depth_buffer: Vec<f32>,
+    hdr_target: Option<ColorAttachment>,
+    color_attachments: Vec<ColorAttachment>,
+    depth_buffer: DepthBuffer,
+    stencil_buffer: Vec<u8>,
 
     width: u32,
     height: u32,
 }
 
+/// Depth values in the storage of a [`DepthFormat`]
+enum DepthBuffer {
+    D16(Vec<u16>),
+    /// 24 bit values in the lower bits
+    D24(Vec<u32>),
+    F32(Vec<f32>),
+}
+
+impl DepthBuffer {
+    fn new(format: DepthFormat, size: usize) -> Self {
+        match format {
+            DepthFormat::D16 => DepthBuffer::D16(vec![0; size]),
+            DepthFormat::D24 => DepthBuffer::D24(vec![0; size]),
+            DepthFormat::F32 => DepthBuffer::F32(vec![0.0; size]),
+        }
+    }
+
+    fn format(&self) -> DepthFormat {
+        match self {
+            DepthBuffer::D16(_) => DepthFormat::D16,
+            DepthBuffer::D24(_) => DepthFormat::D24,
+            DepthBuffer::F32(_) => DepthFormat::F32,
+        }
+    }
+
+    fn resize(&mut self, size: usize) {
+        match self {
+            DepthBuffer::D16(buffer) => buffer.resize(size, 0),
+            DepthBuffer::D24(buffer) => buffer.resize(size, 0),
+            DepthBuffer::F32(buffer) => buffer.resize(size, 0.0),
+        }
+    }
+
+    fn get(&self, idx: usize) -> f32 {
+        match self {
+            DepthBuffer::D16(buffer) => unorm_to_f32(buffer[idx] as u32, 16),
+            DepthBuffer::D24(buffer) => unorm_to_f32(buffer[idx], 24),
+            DepthBuffer::F32(buffer) => buffer[idx],
+        }
+    }
+
+    fn set(&mut self, idx: usize, depth: f32) {
+        match self {
+            DepthBuffer::D16(buffer) => {
+                buffer[idx] = f32_to_unorm(depth, 16) as u16
+            }
+            DepthBuffer::D24(buffer) => buffer[idx] = f32_to_unorm(depth, 24),
+            DepthBuffer::F32(buffer) => buffer[idx] = depth,
+        }
+    }
+
+    fn fill(&mut self, depth: f32) {
+        match self {
+            DepthBuffer::D16(buffer) => {
+                buffer.fill(f32_to_unorm(depth, 16) as u16)
+            }
+            DepthBuffer::D24(buffer) => buffer.fill(f32_to_unorm(depth, 24)),
+            DepthBuffer::F32(buffer) => buffer.fill(depth),
+        }
+    }
+}
+
 impl Framebuffer {
     /// Construct a new framebuffer with the given width and height
     pub fn new(width: u32, height: u32) -> Self {
         let buffer_size = width * height;
         Self {
             color_buffer: vec![0; buffer_size as usize],
-            depth_buffer: vec![0.0; buffer_size as usize],
+            hdr_target: None,
+            color_attachments: Vec::new(),
+            depth_buffer: DepthBuffer::new(
+                DepthFormat::F32,
+                buffer_size as usize,
+            ),
+            stencil_buffer: vec![0; buffer_size as usize],
             width,
             height,
         }
@@ -29,16 +104,138 @@ impl Framebuffer {
 
             let buffer_size = (self.width * self.height) as usize;
             self.color_buffer.resize(buffer_size, 0);
-            self.depth_buffer.resize(buffer_size, 0.0);
+            self.depth_buffer.resize(buffer_size);
+            self.stencil_buffer.resize(buffer_size, 0);
+            for attachment in &mut self.color_attachments {
+                attachment.resize(buffer_size);
+            }
+            if let Some(hdr_target) = &mut self.hdr_target {
+                hdr_target.resize(buffer_size);
+            }
         }
     }
 
+    /// Adds an HDR color target with the given format or removes it with
+    /// `None`. The content of a new target is zero.
+    pub fn set_hdr_format(&mut self, format: Option<ColorFormat>) {
+        if self.hdr_format() != format {
+            let buffer_size = (self.width * self.height) as usize;
+            self.hdr_target = format.map(|format| {
+                ColorAttachment::new(format, buffer_size, Vec4::ZERO)
+            });
+        }
+    }
+
+    pub fn hdr_format(&self) -> Option<ColorFormat> {
+        self.hdr_target.as_ref().map(ColorAttachment::format)
+    }
+
+    /// Sets the linear color of the HDR target at the given position
+    pub fn set_hdr(&mut self, pos: PixelPosition, color: Vec3) {
+        let idx = self.pos_to_idx(pos);
+        if let Some(hdr_target) = &mut self.hdr_target {
+            hdr_target.set(idx, color.extend(1.0));
+        }
+    }
+
+    /// Returns the linear color of the HDR target at the given position
+    pub fn hdr(&self, pos: PixelPosition) -> Option<Vec3> {
+        let idx = self.pos_to_idx(pos);
+        let hdr_target = self.hdr_target.as_ref()?;
+        Some(hdr_target.get(idx).truncate())
+    }
+
+    /// Clears the HDR target to the given linear color
+    pub fn set_hdr_all(&mut self, color: Vec3) {
+        if let Some(hdr_target) = &mut self.hdr_target {
+            for idx in 0..(self.width * self.height) as usize {
+                hdr_target.set(idx, color.extend(1.0));
+            }
+        }
+    }
+
+    /// Tone maps the HDR target into the color buffer
+    pub fn resolve_hdr(&mut self, tone_mapping: &ToneMapping) {
+        if let Some(hdr_target) = &self.hdr_target {
+            for (idx, color) in self.color_buffer.iter_mut().enumerate() {
+                let hdr = hdr_target.get(idx).truncate();
+                *color = color_to_u32(tone_mapping.map(hdr));
+            }
+        }
+    }
+
+    /// Changes how depth values are stored. The depth buffer content is
+    /// reset, so it has to be cleared afterwards.
+    pub fn set_depth_format(&mut self, format: DepthFormat) {
+        if self.depth_format() != format {
+            let buffer_size = (self.width * self.height) as usize;
+            self.depth_buffer = DepthBuffer::new(format, buffer_size);
+        }
+    }
+
+    pub fn depth_format(&self) -> DepthFormat {
+        self.depth_buffer.format()
+    }
+
+    /// Adds a color attachment and returns its index
+    pub fn add_color_attachment(
+        &mut self,
+        format: ColorFormat,
+        clear_value: Vec4,
+    ) -> usize {
+        let buffer_size = (self.width * self.height) as usize;
+        self.color_attachments.push(ColorAttachment::new(
+            format,
+            buffer_size,
+            clear_value,
+        ));
+        self.color_attachments.len() - 1
+    }
+
+    /// Removes all color attachments
+    pub fn remove_color_attachments(&mut self) {
+        self.color_attachments.clear();
+    }
+
+    pub fn color_attachments(&self) -> &[ColorAttachment] {
+        &self.color_attachments
+    }
+
+    /// Clears every color attachment to its clear value
+    pub fn clear_color_attachments(&mut self) {
+        for attachment in &mut self.color_attachments {
+            attachment.clear();
+        }
+    }
+
+    /// Sets the value of the color attachment at the given position
+    pub fn set_attachment(
+        &mut self,
+        attachment: usize,
+        pos: PixelPosition,
+        value: Vec4,
+    ) {
+        let idx = self.pos_to_idx(pos);
+        self.color_attachments[attachment].set(idx, value);
+    }
+
+    /// Returns the value of the color attachment at the given position
+    pub fn attachment(&self, attachment: usize, pos: PixelPosition) -> Vec4 {
+        self.color_attachments[attachment].get(self.pos_to_idx(pos))
+    }
+
     /// Sets the color at the given position to the specified RGB color
     pub fn set_color_rgb(&mut self, pos: PixelPosition, color: Color) {
         let color = color_to_u32(color);
         self.set_color(pos, color);
     }
 
+    /// Returns the RGB color at the given position
+    pub fn color_rgb(&self, pos: PixelPosition) -> Color {
+        let color = self.color_buffer[self.pos_to_idx(pos)];
+        ((color >> 16) as u8, (color >> 8) as u8, color as u8)
+    }
+
     /// Clears the color buffer to the given color
     pub fn set_color_rgb_all(&mut self, color: Color) {
         let color = color_to_u32(color);
@@ -54,9 +251,12 @@ impl Framebuffer {
 
     /// Clears the depth buffer to the given value
     pub fn set_depth_all(&mut self, depth: f32) {
-        for d in &mut self.depth_buffer {
-            *d = depth;
-        }
+        self.depth_buffer.fill(depth);
+    }
+
+    /// Clears the stencil buffer to the given value
+    pub fn set_stencil_all(&mut self, value: u8) {
+        self.stencil_buffer.fill(value);
     }
 
     /// Sets the color at the given position to the specified color
@@ -65,15 +265,27 @@ impl Framebuffer {
         self.color_buffer[idx] = color;
     }
 
-    /// Sets the depth at the given position to the specified depth
+    /// Sets the depth at the given position to the specified depth. The
+    /// depth is quantized to the depth format.
     pub fn set_depth(&mut self, pos: PixelPosition, depth: f32) {
         let idx = self.pos_to_idx(pos);
-        self.depth_buffer[idx] = depth;
+        self.depth_buffer.set(idx, depth);
     }
 
     /// Return the depth on the given position
     pub fn depth(&self, pos: PixelPosition) -> f32 {
-        self.depth_buffer[self.pos_to_idx(pos)]
+        self.depth_buffer.get(self.pos_to_idx(pos))
+    }
+
+    /// Sets the stencil value at the given position
+    pub fn set_stencil(&mut self, pos: PixelPosition, value: u8) {
+        let idx = self.pos_to_idx(pos);
+        self.stencil_buffer[idx] = value;
+    }
+
+    /// Return the stencil value on the given position
+    pub fn stencil(&self, pos: PixelPosition) -> u8 {
+        self.stencil_buffer[self.pos_to_idx(pos)]
     }
 
     /// Returns a reference to the color buffer
@@ -81,6 +293,52 @@ impl Framebuffer {
         &self.color_buffer
     }
 
+    /// Copies the color buffer into an image
+    pub fn to_rgb_image(&self) -> image::RgbImage {
+        image::RgbImage::from_fn(self.width, self.height, |x, y| {
+            let color = self.color_buffer[self.pos_to_idx((x, y))];
+            image::Rgb([(color >> 16) as u8, (color >> 8) as u8, color as u8])
+        })
+    }
+
+    /// Copies the colors into a floating point image. The linear colors of
+    /// the HDR target are used if there is one, otherwise the color buffer
+    /// is decoded from sRGB.
+    pub fn to_rgb32f_image(&self) -> image::Rgb32FImage {
+        image::Rgb32FImage::from_fn(self.width, self.height, |x, y| {
+            let color = self
+                .hdr((x, y))
+                .unwrap_or_else(|| color_to_linear(self.color_rgb((x, y))));
+            image::Rgb(color.to_array())
+        })
+    }
+
+    /// Writes the colors to an image file. EXR files get the floating point
+    /// colors of [`Framebuffer::to_rgb32f_image`], every other format that
+    /// the image crate knows from the extension, like PNG or PPM, gets the
+    /// color buffer.
+    pub fn save_color(&self, file_path: &str) -> Result<()> {
+        if has_extension(file_path, "exr") {
+            self.to_rgb32f_image().save(file_path)?;
+        } else {
+            self.to_rgb_image().save(file_path)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the RGB channels of a color attachment into an image. Values
+    /// are clamped to [0, 1].
+    pub fn attachment_to_rgb_image(
+        &self,
+        attachment: usize,
+    ) -> image::RgbImage {
+        image::RgbImage::from_fn(self.width, self.height, |x, y| {
+            let value = self.attachment(attachment, (x, y)).truncate();
+            let value = (value.clamp(Vec3::ZERO, Vec3::ONE) * 255.0).round();
+            image::Rgb(value.to_array().map(|c| c as u8))
+        })
+    }
+
     pub fn width(&self) -> u32 {
         self.width
     }
@@ -90,8 +348,8 @@ impl Framebuffer {
     }
 
     fn pos_to_idx(&self, pos: PixelPosition) -> usize {
-        let x = pos.0 as u32;
-        let y = pos.1 as u32;
+        let x = pos.0;
+        let y = pos.1;
         (x + y * self.width) as usize
     }
 
@@ -105,6 +363,13 @@ fn color_to_u32(color: Color) -> u32 {
     let red = color.0 as u32;
     let green = color.1 as u32;
     let blue = color.2 as u32;
-    let color = blue | (green << 8) | (red << 16);
-    color
+    blue | (green << 8) | (red << 16)
+}
+
+/// Returns whether a file path ends with the extension, ignoring the case
+pub(crate) fn has_extension(file_path: &str, extension: &str) -> bool {
+    std::path::Path::new(file_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
 }
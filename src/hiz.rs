@@ -0,0 +1,214 @@
+use crate::prelude::*;
+
+/// Width and height in pixels of the tiles of the finest pyramid level
+pub const HIZ_TILE_SIZE: u32 = 8;
+
+/// A coarse min/max depth pyramid over the depth buffer of a framebuffer.
+/// Level 0 stores the minimum and maximum depth of every tile of
+/// [`HIZ_TILE_SIZE`] pixels, each following level combines 2x2 tiles of the
+/// previous one.
+///
+/// Geometry whose closest depth is further away than the furthest depth of
+/// all tiles it covers is hidden. Depending on the depth compare function
+/// closer means smaller or larger. Because depth values only get closer while
+/// drawing, an outdated pyramid never rejects visible geometry, it just
+/// rejects less.
+#[derive(Default)]
+pub struct DepthPyramid {
+    levels: Vec<PyramidLevel>,
+    /// Tiles of level 0 whose pixels changed since they were computed
+    dirty: Vec<bool>,
+    any_dirty: bool,
+}
+
+#[derive(Default)]
+struct PyramidLevel {
+    width: u32,
+    height: u32,
+    min: Vec<f32>,
+    max: Vec<f32>,
+}
+
+impl PyramidLevel {
+    fn new(width: u32, height: u32, depth: f32) -> Self {
+        let size = (width * height) as usize;
+        Self {
+            width,
+            height,
+            min: vec![depth; size],
+            max: vec![depth; size],
+        }
+    }
+}
+
+impl DepthPyramid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resets the pyramid for a framebuffer of the given size that was
+    /// cleared to `depth`
+    pub fn reset(&mut self, width: u32, height: u32, depth: f32) {
+        self.levels.clear();
+
+        let mut level_width = width.div_ceil(HIZ_TILE_SIZE).max(1);
+        let mut level_height = height.div_ceil(HIZ_TILE_SIZE).max(1);
+        loop {
+            self.levels.push(PyramidLevel::new(
+                level_width,
+                level_height,
+                depth,
+            ));
+            if level_width == 1 && level_height == 1 {
+                break;
+            }
+            level_width = level_width.div_ceil(2);
+            level_height = level_height.div_ceil(2);
+        }
+
+        self.dirty.clear();
+        self.dirty.resize(self.levels[0].min.len(), false);
+        self.any_dirty = false;
+    }
+
+    /// Builds the whole pyramid from the depth buffer
+    pub fn build(&mut self, framebuffer: &Framebuffer) {
+        self.reset(framebuffer.width(), framebuffer.height(), 0.0);
+        self.dirty.fill(true);
+        self.any_dirty = true;
+        self.update(framebuffer);
+    }
+
+    /// Marks the tile that contains the pixel as changed
+    pub fn mark_dirty(&mut self, pos: PixelPosition) {
+        let level = &self.levels[0];
+        let idx = pos.0 / HIZ_TILE_SIZE + pos.1 / HIZ_TILE_SIZE * level.width;
+        self.dirty[idx as usize] = true;
+        self.any_dirty = true;
+    }
+
+    /// Recomputes the changed tiles and all coarser levels
+    pub fn update(&mut self, framebuffer: &Framebuffer) {
+        if !self.any_dirty {
+            return;
+        }
+
+        let level_width = self.levels[0].width;
+        for idx in 0..self.dirty.len() {
+            if self.dirty[idx] {
+                let idx = idx as u32;
+                self.update_tile(
+                    framebuffer,
+                    idx % level_width,
+                    idx / level_width,
+                );
+            }
+        }
+        self.any_dirty = false;
+
+        for i in 1..self.levels.len() {
+            let (finer, coarser) = self.levels.split_at_mut(i);
+            let finer = &finer[i - 1];
+            let coarser = &mut coarser[0];
+            for y in 0..coarser.height {
+                for x in 0..coarser.width {
+                    let mut min = f32::MAX;
+                    let mut max = f32::MIN;
+                    for fy in (2 * y)..(2 * y + 2).min(finer.height) {
+                        for fx in (2 * x)..(2 * x + 2).min(finer.width) {
+                            let idx = (fx + fy * finer.width) as usize;
+                            min = min.min(finer.min[idx]);
+                            max = max.max(finer.max[idx]);
+                        }
+                    }
+                    let idx = (x + y * coarser.width) as usize;
+                    coarser.min[idx] = min;
+                    coarser.max[idx] = max;
+                }
+            }
+        }
+    }
+
+    /// Returns the minimum and maximum depth of the level 0 tile at the given
+    /// tile coordinate. The tile is recomputed first if it changed.
+    pub fn tile_depth(
+        &mut self,
+        framebuffer: &Framebuffer,
+        tile_x: u32,
+        tile_y: u32,
+    ) -> (f32, f32) {
+        let idx = (tile_x + tile_y * self.levels[0].width) as usize;
+        if self.dirty[idx] {
+            self.update_tile(framebuffer, tile_x, tile_y);
+        }
+        (self.levels[0].min[idx], self.levels[0].max[idx])
+    }
+
+    /// Returns true if everything in the pixel rectangle from `min` to `max`
+    /// (inclusive) is closer than `depth`, so that geometry with `depth` as
+    /// its closest depth is hidden. `greater_wins` tells whether larger depth
+    /// values are closer.
+    pub fn is_occluded(
+        &self,
+        min: UVec2,
+        max: UVec2,
+        depth: f32,
+        greater_wins: bool,
+    ) -> bool {
+        if self.levels.is_empty() {
+            return false;
+        }
+
+        // Use the finest level where the rectangle covers at most 2x2 tiles
+        let mut level_idx = 0;
+        let mut tile_size = HIZ_TILE_SIZE;
+        while level_idx + 1 < self.levels.len()
+            && (max.x / tile_size - min.x / tile_size > 1
+                || max.y / tile_size - min.y / tile_size > 1)
+        {
+            level_idx += 1;
+            tile_size *= 2;
+        }
+
+        let level = &self.levels[level_idx];
+        let max_tile_x = (max.x / tile_size).min(level.width - 1);
+        let max_tile_y = (max.y / tile_size).min(level.height - 1);
+        for y in (min.y / tile_size)..=max_tile_y {
+            for x in (min.x / tile_size)..=max_tile_x {
+                let idx = (x + y * level.width) as usize;
+                let visible = if greater_wins {
+                    depth >= level.min[idx]
+                } else {
+                    depth <= level.max[idx]
+                };
+                if visible {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn update_tile(&mut self, framebuffer: &Framebuffer, x: u32, y: u32) {
+        let x0 = x * HIZ_TILE_SIZE;
+        let y0 = y * HIZ_TILE_SIZE;
+        let x1 = (x0 + HIZ_TILE_SIZE).min(framebuffer.width());
+        let y1 = (y0 + HIZ_TILE_SIZE).min(framebuffer.height());
+
+        let mut min = f32::MAX;
+        let mut max = f32::MIN;
+        for py in y0..y1 {
+            for px in x0..x1 {
+                let depth = framebuffer.depth((px, py));
+                min = min.min(depth);
+                max = max.max(depth);
+            }
+        }
+
+        let level = &mut self.levels[0];
+        let idx = (x + y * level.width) as usize;
+        level.min[idx] = min;
+        level.max[idx] = max;
+        self.dirty[idx] = false;
+    }
+}
@@ -0,0 +1,173 @@
+use crate::prelude::*;
+
+/// Shape of the light emitted by a [`Light`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    /// Parallel light from infinitely far away, like the sun. `direction` is
+    /// the direction in which the light travels.
+    Directional { direction: Vec3 },
+    /// Light that shines equally in all directions from a point
+    Point { position: Vec3 },
+    /// Light from a point that is limited to a cone around `direction`. The
+    /// light is at full intensity inside of `inner_angle` and fades out
+    /// until `outer_angle`. Angles are in radians from the cone axis.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+/// A light source of a scene. Point and spot lights fall off smoothly with
+/// the distance and reach zero at `range`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Linear color
+    pub color: Vec3,
+    /// Factor the color is scaled with
+    pub intensity: f32,
+    /// Distance after which the light has no effect. Ignored by directional
+    /// lights.
+    pub range: f32,
+}
+
+impl Light {
+    /// Creates a white directional light
+    pub fn directional(direction: Vec3) -> Self {
+        Self::new(LightKind::Directional {
+            direction: direction.normalize(),
+        })
+    }
+
+    /// Creates a white point light
+    pub fn point(position: Vec3, range: f32) -> Self {
+        Self {
+            range,
+            ..Self::new(LightKind::Point { position })
+        }
+    }
+
+    /// Creates a white spot light
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    ) -> Self {
+        Self {
+            range,
+            ..Self::new(LightKind::Spot {
+                position,
+                direction: direction.normalize(),
+                inner_angle,
+                outer_angle,
+            })
+        }
+    }
+
+    fn new(kind: LightKind) -> Self {
+        Self {
+            kind,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            range: f32::INFINITY,
+        }
+    }
+
+    pub fn with_color(mut self, color: Vec3) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Returns the position of point and spot lights
+    pub fn position(&self) -> Option<Vec3> {
+        match self.kind {
+            LightKind::Directional { .. } => None,
+            LightKind::Point { position }
+            | LightKind::Spot { position, .. } => Some(position),
+        }
+    }
+
+    /// Returns the factor the light is scaled with at the given distance
+    pub fn attenuation(&self, distance: f32) -> f32 {
+        let falloff = (1.0 - (distance / self.range).powi(2)).max(0.0);
+        falloff * falloff
+    }
+
+    /// Returns the normalized direction from `position` to the light and the
+    /// light that arrives there, or `None` if the light doesn't reach it
+    pub fn incident(&self, position: Vec3) -> Option<(Vec3, Vec3)> {
+        let radiance = self.color * self.intensity;
+        let (to_light, distance) = match self.kind {
+            LightKind::Directional { direction } => {
+                return Some((-direction, radiance));
+            }
+            LightKind::Point {
+                position: light_position,
+            }
+            | LightKind::Spot {
+                position: light_position,
+                ..
+            } => {
+                let to_light = light_position - position;
+                let distance = to_light.length();
+                if distance >= self.range || distance == 0.0 {
+                    return None;
+                }
+                (to_light / distance, distance)
+            }
+        };
+
+        let mut factor = self.attenuation(distance);
+        if let LightKind::Spot {
+            direction,
+            inner_angle,
+            outer_angle,
+            ..
+        } = self.kind
+        {
+            let cos_angle = direction.dot(-to_light);
+            let (cos_inner, cos_outer) = (inner_angle.cos(), outer_angle.cos());
+            if cos_angle <= cos_outer {
+                return None;
+            }
+            factor *= smoothstep(cos_outer, cos_inner, cos_angle);
+        }
+        Some((to_light, radiance * factor))
+    }
+}
+
+/// Lambert shading of a surface with the given albedo. The lights are summed
+/// on top of the ambient light.
+pub fn shade_lambert<'a>(
+    albedo: Vec3,
+    position: Vec3,
+    normal: Vec3,
+    ambient: Vec3,
+    lights: impl IntoIterator<Item = &'a Light>,
+) -> Vec3 {
+    let mut light = ambient;
+    for l in lights {
+        if let Some((to_light, radiance)) = l.incident(position) {
+            light += radiance * normal.dot(to_light).max(0.0);
+        }
+    }
+    albedo * light
+}
+
+/// Smooth Hermite interpolation from 0 at `edge0` to 1 at `edge1`
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x >= edge1 { 1.0 } else { 0.0 };
+    }
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -1,56 +1,323 @@
+mod attachment;
+mod bounds;
 mod camera;
+mod camera_path;
+mod controller;
+mod cubemap;
+mod deferred;
+mod depth;
+mod environment;
+mod fog;
+mod font;
 mod framebuffer;
+mod hiz;
+mod light;
+mod mesh;
+mod pbr;
+mod postprocess;
+mod primitives;
 mod rasterizer;
+mod shader;
+mod ssao;
+mod stencil;
 mod texture;
+mod tonemap;
 mod types;
+mod vertex_cache;
 
 pub mod prelude {
+    pub use crate::attachment::*;
+    pub use crate::bounds::*;
     pub use crate::camera::*;
+    pub use crate::camera_path::*;
+    pub use crate::controller::*;
+    pub use crate::cubemap::*;
+    pub use crate::deferred::*;
+    pub use crate::depth::*;
+    pub use crate::environment::*;
+    pub use crate::fog::*;
+    pub use crate::font::*;
     pub use crate::framebuffer::*;
+    pub use crate::hiz::*;
+    pub use crate::light::*;
+    pub use crate::mesh::*;
+    pub use crate::pbr::*;
+    pub use crate::postprocess::*;
+    pub use crate::primitives::*;
     pub use crate::rasterizer::*;
+    pub use crate::shader::*;
+    pub use crate::ssao::*;
+    pub use crate::stencil::*;
     pub use crate::texture::*;
+    pub use crate::tonemap::*;
     pub use crate::types::*;
+    pub use crate::vertex_cache::*;
     pub use anyhow::Result;
-    pub use glam::{IVec2, Mat4, Vec2, Vec3, Vec4};
+    pub use glam::{IVec2, Mat4, Quat, UVec2, Vec2, Vec3, Vec4};
     pub use image;
 }
 
 use crate::prelude::*;
 
+use anyhow::Context;
 use softbuffer::GraphicsContext;
-use std::time::SystemTime;
-use tobj;
+use std::time::Instant;
 use winit::event::VirtualKeyCode;
 use winit::event_loop::EventLoop;
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
+/// File the viewer saves recorded camera paths to if no path is given
+const DEFAULT_CAMERA_PATH: &str = "camera_path.txt";
+
+/// Face size of the skybox if it is created from the environment
+const SKYBOX_SIZE: u32 = 256;
+
+/// Start of the file names of the screenshots the viewer saves
+const SCREENSHOT_PREFIX: &str = "screenshot";
+
+/// Distance of the overlay text from the corner of the window in pixels
+const OVERLAY_MARGIN: u32 = 8;
+
+/// Meshes that make up the model
+const MODEL_PATHS: [&str; 3] = [
+    "assets/african_head/african_head.obj",
+    "assets/african_head/african_head_eye_inner.obj",
+    "assets/african_head/african_head_eye_outer.obj",
+];
+
 fn main() -> Result<()> {
+    let options = Options::from_args(std::env::args().skip(1))?;
+    let mut model = load_obj(MODEL_PATHS[0])?;
+    for file_path in &MODEL_PATHS[1..] {
+        model.append(load_obj(file_path)?);
+    }
+
+    // The skybox and the image based lighting show the same surroundings if
+    // only one of them is given
+    let skybox = match &options.skybox {
+        Some(file_paths) => Some(load_skybox(file_paths)?),
+        None => None,
+    };
+    let radiance = match (&options.environment, &skybox) {
+        (Some(file_path), _) => EquirectMap::from_file(file_path)?,
+        (None, Some(skybox)) => {
+            skybox.to_equirect(4 * skybox.size().next_power_of_two())
+        }
+        (None, None) => procedural_sky(),
+    };
+    let skybox = skybox
+        .unwrap_or_else(|| Cubemap::from_equirect(&radiance, SKYBOX_SIZE));
+    let lut = match &options.lut {
+        Some(file_path) => Some(Lut3d::from_file(file_path)?),
+        None => None,
+    };
+    let scene = Scene {
+        model,
+        environment: Environment::new(radiance),
+        skybox: Skybox::new(skybox),
+        lut,
+    };
+
+    if options.headless {
+        render_headless(&options, &scene)
+    } else {
+        run_viewer(options, scene)
+    }
+}
+
+/// Everything that is drawn
+struct Scene {
+    model: Model,
+    /// Image based lighting of the physically based shading
+    environment: Environment,
+    /// Drawn behind the model and reflected by it
+    skybox: Skybox,
+    /// Color grading lookup table
+    lut: Option<Lut3d>,
+}
+
+/// Loads a cubemap from six comma separated image files in the order +X, -X,
+/// +Y, -Y, +Z, -Z
+fn load_skybox(file_paths: &str) -> Result<Cubemap> {
+    let file_paths: Vec<_> = file_paths.split(',').map(str::trim).collect();
+    let Ok(file_paths) = <[&str; 6]>::try_from(file_paths) else {
+        anyhow::bail!("--skybox needs six comma separated images");
+    };
+    Cubemap::from_files(file_paths)
+}
+
+/// Command line options
+struct Options {
+    /// Render the camera path to image files instead of opening a window
+    headless: bool,
+    /// Camera path to load
+    camera_path: Option<String>,
+    /// Equirectangular HDR image used for image based lighting
+    environment: Option<String>,
+    /// Six comma separated cubemap faces drawn as the skybox
+    skybox: Option<String>,
+    /// `.cube` lookup table used for color grading
+    lut: Option<String>,
+    /// Directory the frames are written to in headless mode
+    output_dir: String,
+    /// Frames per second in headless mode
+    fps: f32,
+    /// Rotation of the camera controllers in radians per pixel of mouse
+    /// movement, their defaults if not given
+    sensitivity: Option<f32>,
+    /// Invert the vertical mouse axis of the fly camera
+    invert_y: bool,
+    width: u32,
+    height: u32,
+}
+
+impl Options {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut options = Self {
+            headless: false,
+            camera_path: None,
+            environment: None,
+            skybox: None,
+            lut: None,
+            output_dir: "frames".to_string(),
+            fps: 30.0,
+            sensitivity: None,
+            invert_y: false,
+            width: 800,
+            height: 600,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow::anyhow!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--path" => options.camera_path = Some(value()?),
+                "--environment" => options.environment = Some(value()?),
+                "--skybox" => options.skybox = Some(value()?),
+                "--lut" => options.lut = Some(value()?),
+                "--output" => options.output_dir = value()?,
+                "--fps" => options.fps = value()?.parse()?,
+                "--sensitivity" => {
+                    options.sensitivity = Some(value()?.parse()?)
+                }
+                "--invert-y" => options.invert_y = true,
+                "--width" => options.width = value()?.parse()?,
+                "--height" => options.height = value()?.parse()?,
+                _ => anyhow::bail!("Unknown argument {}", arg),
+            }
+        }
+
+        if !options.fps.is_finite() || options.fps <= 0.0 {
+            anyhow::bail!("--fps has to be a positive number");
+        }
+        if options.sensitivity.is_some_and(|sensitivity| {
+            !sensitivity.is_finite() || sensitivity <= 0.0
+        }) {
+            anyhow::bail!("--sensitivity has to be a positive number");
+        }
+        if options.headless && options.camera_path.is_none() {
+            anyhow::bail!("--headless needs a camera path (--path <file>)");
+        }
+        Ok(options)
+    }
+}
+
+/// Renders every frame of the camera path and writes it as PNG into the
+/// output directory. Frames are sampled at fixed time steps, so the same path
+/// always produces the same images.
+fn render_headless(options: &Options, scene: &Scene) -> Result<()> {
+    let path = CameraPath::load(options.camera_path.as_ref().unwrap())?;
+    if path.is_empty() {
+        anyhow::bail!("The camera path has no keyframes");
+    }
+    std::fs::create_dir_all(&options.output_dir)?;
+
+    let mut camera = Camera::new(Vec3::new(0.0, 0.0, 3.0));
+    camera.set_viewport_size(options.width, options.height);
+    let mut rasterizer = create_rasterizer();
+    rasterizer.resize(options.width as u16, options.height as u16);
+
+    let duration = path.end_time() - path.start_time();
+    let frame_count = ((duration * options.fps).floor() as u32)
+        .checked_add(1)
+        .ok_or_else(|| anyhow::anyhow!("The camera path is too long"))?;
+    let mut meshes_culled = 0;
+    let mut triangles_culled = 0;
+    let mut triangles_occluded = 0;
+    for frame in 0..frame_count {
+        let time = path.start_time() + frame as f32 / options.fps;
+        path.sample(time)
+            .with_context(|| format!("Could not sample the path at {}", time))?
+            .apply(&mut camera);
+
+        draw_scene(
+            &mut rasterizer,
+            &camera,
+            scene,
+            &SceneSettings::default(),
+            time,
+        );
+        resolve_frame(&mut rasterizer);
+        meshes_culled += rasterizer.stats().meshes_culled;
+        triangles_culled += rasterizer.stats().triangles_culled;
+        triangles_occluded += rasterizer.stats().triangles_occluded;
+
+        let file_path =
+            format!("{}/frame_{:05}.png", options.output_dir, frame);
+        rasterizer.framebuffer().save_color(&file_path)?;
+    }
+    println!("Rendered {} frames to {}", frame_count, options.output_dir);
+    println!(
+        "Culled {} meshes and {} triangles, {} triangles were occluded",
+        meshes_culled, triangles_culled, triangles_occluded
+    );
+
+    Ok(())
+}
+
+fn run_viewer(options: Options, scene: Scene) -> Result<()> {
     let mut input = WinitInputHelper::new();
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
     let mut graphics_context = unsafe { GraphicsContext::new(window).unwrap() };
     let mut camera = Camera::new(Vec3::new(0.0, 0.0, 3.0));
-    let mut rasterizer = Rasterizer::new();
-    rasterizer.set_origin(DrawOrigin::BottomLeft);
-
-    let model = load_obj("assets/african_head/african_head.obj")?;
+    let mut fly_controller = FlyController::new();
+    let mut orbit_controller = OrbitController::new(Vec3::ZERO, 3.0);
+    if let Some(sensitivity) = options.sensitivity {
+        fly_controller.set_sensitivity(sensitivity);
+        orbit_controller.set_sensitivity(sensitivity);
+    }
+    fly_controller.set_invert_y(options.invert_y);
+    let mut camera_mode = CameraMode::Fly;
+    let mut rasterizer = create_rasterizer();
 
     let mut wireframe = false;
-    let mut light_dir = Vec3::new(0.0, 0.0, -1.0);
-
-    rasterizer.set_clear_color((81, 141, 237));
-    rasterizer.set_depth_value(f32::MIN);
+    let mut overlay = true;
+    // Smoothed time between frames in seconds for the overlay
+    let mut frame_time = 1.0 / 60.0;
+    let mut settings = SceneSettings::default();
+
+    // Camera path recording and playback
+    let camera_path_file = options
+        .camera_path
+        .clone()
+        .unwrap_or_else(|| DEFAULT_CAMERA_PATH.to_string());
+    let mut camera_path = match &options.camera_path {
+        Some(file_path) => CameraPath::load(file_path)?,
+        None => CameraPath::new(),
+    };
+    let mut recording_start: Option<Instant> = None;
+    let mut playback_start: Option<Instant> = None;
 
-    let start_time = SystemTime::now();
+    let start_time = Instant::now();
+    let mut last_frame = Instant::now();
+    let mut last_stats_report = Instant::now();
 
     event_loop.run(move |event, _, control_flow| {
-        let current_time = SystemTime::now();
-        let time_passed = current_time.duration_since(start_time).unwrap();
-        let light_dir_x =
-            f32::sin(time_passed.as_millis() as f32 * 0.002).abs();
-        light_dir.x = light_dir_x;
-
         if input.update(&event) {
             if input.key_pressed(VirtualKeyCode::Escape) {
                 control_flow.set_exit();
@@ -60,69 +327,271 @@ fn main() -> Result<()> {
                 wireframe = !wireframe;
             }
 
-            let move_speed = 5.0;
-            if input.key_held(VirtualKeyCode::W) {
-                camera.move_forward(move_speed);
+            let now = Instant::now();
+            let delta_time = now.duration_since(last_frame).as_secs_f32();
+            last_frame = now;
+
+            let (width, height) = {
+                let size = graphics_context.window().inner_size();
+                (size.width, size.height)
+            };
+
+            if input.key_pressed(VirtualKeyCode::F2) {
+                camera_mode = match camera_mode {
+                    CameraMode::Fly => {
+                        orbit_controller.attach(&mut camera);
+                        CameraMode::Orbit
+                    }
+                    CameraMode::Orbit => CameraMode::Fly,
+                };
+            }
+
+            if input.key_pressed(VirtualKeyCode::F3) {
+                orbit_controller.set_arcball(!orbit_controller.arcball());
+            }
+
+            if input.key_pressed(VirtualKeyCode::F4) {
+                rasterizer.set_hiz(!rasterizer.hiz());
+            }
+
+            // F8 toggles reversed Z and F9 cycles through the depth formats
+            if input.key_pressed(VirtualKeyCode::F8) {
+                let depth_state = if rasterizer.depth_state().reversed_z {
+                    DepthState::default()
+                } else {
+                    DepthState::reversed()
+                };
+                rasterizer.set_depth_state(depth_state);
+                rasterizer.set_depth_value(depth_state.clear_value());
+                camera.set_reversed_z(depth_state.reversed_z);
             }
-            if input.key_held(VirtualKeyCode::S) {
-                camera.move_backward(move_speed);
+            if input.key_pressed(VirtualKeyCode::F9) {
+                let format = match rasterizer.depth_format() {
+                    DepthFormat::D16 => DepthFormat::D24,
+                    DepthFormat::D24 => DepthFormat::F32,
+                    DepthFormat::F32 => DepthFormat::D16,
+                };
+                rasterizer.set_depth_format(format);
+            }
+
+            // F10 toggles the outline and F11 cycles through the lighting
+            if input.key_pressed(VirtualKeyCode::F10) {
+                settings.outline = !settings.outline;
             }
-            if input.key_held(VirtualKeyCode::A) {
-                camera.move_left(move_speed);
+            if input.key_pressed(VirtualKeyCode::F11) {
+                settings.lighting = match settings.lighting {
+                    LightingMode::Directional => LightingMode::Forward,
+                    LightingMode::Forward => LightingMode::Deferred,
+                    LightingMode::Deferred => LightingMode::Pbr,
+                    LightingMode::Pbr => LightingMode::Directional,
+                };
             }
-            if input.key_held(VirtualKeyCode::D) {
-                camera.move_right(move_speed);
+            // F12 toggles the reflections of the skybox
+            if input.key_pressed(VirtualKeyCode::F12) {
+                settings.reflections = !settings.reflections;
             }
 
-            // Draw
-            let (width, height) = {
-                let size = graphics_context.window().inner_size();
-                (size.width, size.height)
+            // T cycles through the tone mapping operators, minus and plus
+            // change the exposure by half a stop
+            if input.key_pressed(VirtualKeyCode::T) {
+                let tone_mapping = &mut settings.tone_mapping;
+                tone_mapping.operator = match tone_mapping.operator {
+                    ToneMapOperator::Clamp => ToneMapOperator::Reinhard,
+                    ToneMapOperator::Reinhard => ToneMapOperator::Aces,
+                    ToneMapOperator::Aces => ToneMapOperator::Clamp,
+                };
+            }
+            if input.key_pressed(VirtualKeyCode::Minus) {
+                settings.tone_mapping.exposure -= 0.5;
+            }
+            if input.key_pressed(VirtualKeyCode::Equals) {
+                settings.tone_mapping.exposure += 0.5;
+            }
+
+            // B toggles the bloom, V the vignette and G the color grading
+            if input.key_pressed(VirtualKeyCode::B) {
+                settings.bloom = !settings.bloom;
+            }
+            if input.key_pressed(VirtualKeyCode::V) {
+                settings.vignette = !settings.vignette;
+            }
+            if input.key_pressed(VirtualKeyCode::G) {
+                settings.color_grade = !settings.color_grade;
+            }
+
+            // F cycles through the fog modes and H toggles the height fog
+            if input.key_pressed(VirtualKeyCode::F) {
+                settings.fog = match settings.fog {
+                    None => Some(FogMode::Linear),
+                    Some(FogMode::Linear) => Some(FogMode::Exp),
+                    Some(FogMode::Exp) => Some(FogMode::Exp2),
+                    Some(FogMode::Exp2) => None,
+                };
+            }
+            if input.key_pressed(VirtualKeyCode::H) {
+                settings.height_fog = !settings.height_fog;
+            }
+
+            // O toggles the ambient occlusion
+            if input.key_pressed(VirtualKeyCode::O) {
+                settings.ssao = !settings.ssao;
+            }
+
+            // Tab toggles the overlay
+            if input.key_pressed(VirtualKeyCode::Tab) {
+                overlay = !overlay;
+            }
+
+            // P saves the frame with the overlay, if it is shown, once it is
+            // drawn
+            let take_screenshot = input.key_pressed(VirtualKeyCode::P);
+
+            // F5 starts and stops recording, K adds a keyframe, F6 saves the
+            // path and F7 plays it back
+            if input.key_pressed(VirtualKeyCode::F5) {
+                recording_start = match recording_start {
+                    Some(_) => None,
+                    None => {
+                        camera_path.clear();
+                        camera_path.add_keyframe(CameraKeyframe::from_camera(
+                            &camera, 0.0,
+                        ));
+                        Some(now)
+                    }
+                };
+            }
+            if let Some(recording_start) = recording_start {
+                if input.key_pressed(VirtualKeyCode::K) {
+                    let time =
+                        now.duration_since(recording_start).as_secs_f32();
+                    camera_path.add_keyframe(CameraKeyframe::from_camera(
+                        &camera, time,
+                    ));
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::F6) {
+                match camera_path.save(&camera_path_file) {
+                    Ok(()) => {
+                        println!("Saved camera path to {}", camera_path_file)
+                    }
+                    Err(err) => println!("{:#}", err),
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::F7) && !camera_path.is_empty()
+            {
+                recording_start = None;
+                playback_start = match playback_start {
+                    Some(_) => None,
+                    None => Some(now),
+                };
+            }
+
+            // Projection and preset views like in common DCC tools
+            if input.key_pressed(VirtualKeyCode::Numpad5) {
+                let projection = match camera.projection() {
+                    Projection::Perspective => {
+                        let distance = camera
+                            .position()
+                            .distance(orbit_controller.target())
+                            .max(camera.near());
+                        Projection::Orthographic {
+                            height: camera.view_height(distance),
+                        }
+                    }
+                    Projection::Orthographic { .. } => Projection::Perspective,
+                };
+                camera.set_projection(projection);
+                camera.set_zoom(1.0);
+            }
+
+            let preset = if input.key_pressed(VirtualKeyCode::Numpad1) {
+                Some(ViewPreset::Front)
+            } else if input.key_pressed(VirtualKeyCode::Numpad3) {
+                Some(ViewPreset::Side)
+            } else if input.key_pressed(VirtualKeyCode::Numpad7) {
+                Some(ViewPreset::Top)
+            } else {
+                None
             };
+            if let Some(preset) = preset {
+                let target = orbit_controller.target();
+                let distance = camera.position().distance(target);
+                camera.set_view_preset(preset, target, distance);
+                if camera_mode == CameraMode::Orbit {
+                    orbit_controller.attach(&mut camera);
+                }
+            }
 
-            let viewport_mat = Mat4::orthographic_rh_gl(
-                width as f32,
-                0.0,
-                0.0,
-                height as f32,
-                255.0,
-                0.0,
-            )
-            .inverse();
-            let view_mat = camera.view_mat();
-            let mut proj_mat = Mat4::IDENTITY;
-            // proj_mat.w_axis.z = -1.0 / camera.position().z;
+            if let Some(start) = playback_start {
+                let time = camera_path.start_time()
+                    + now.duration_since(start).as_secs_f32();
+                match camera_path.sample(time).with_context(|| {
+                    format!("Could not sample the path at {}", time)
+                }) {
+                    Ok(keyframe) => keyframe.apply(&mut camera),
+                    Err(err) => {
+                        println!("{:#}", err);
+                        playback_start = None;
+                    }
+                }
+                if time >= camera_path.end_time() {
+                    playback_start = None;
+                }
+            } else {
+                match camera_mode {
+                    CameraMode::Fly => {
+                        fly_controller.update(&mut camera, &input, delta_time)
+                    }
+                    CameraMode::Orbit => orbit_controller.update(
+                        &mut camera,
+                        &input,
+                        (width, height),
+                    ),
+                }
+            }
 
+            // Draw
+            camera.set_viewport_size(width, height);
             rasterizer.resize(width as u16, height as u16);
-            rasterizer.clear();
-
-            for mesh in &model.meshes {
-                let vertices = &mesh.vertices;
-                assert!(vertices.len() % 3 == 0);
-
-                for i in (0..vertices.len()).step_by(3) {
-                    let mut triangle_coords = [Vec3::ZERO; 3];
-                    let mut tex_coords = [Vec2::ZERO; 3];
-                    let mut world_coords = [Vec3::ZERO; 3];
-                    // Calculate vertex position
-                    for j in 0..3 {
-                        let v = vertices[i + j];
-                        // let p = m2v((viewport_mat * proj_mat)
-                        //     * Mat4::from_translation(v.position));
-                        let p = (viewport_mat * view_mat)
-                            .transform_point3(v.position);
-                        triangle_coords[j] = p;
-                        tex_coords[j] = v.tex_coord;
-                        world_coords[j] = v.position;
+            let time = now.duration_since(start_time).as_secs_f32();
+            draw_scene(&mut rasterizer, &camera, &scene, &settings, time);
+            frame_time += (delta_time - frame_time) * 0.1;
+            if overlay {
+                draw_overlay(
+                    &mut rasterizer,
+                    &camera,
+                    camera_mode,
+                    &settings,
+                    frame_time,
+                );
+            }
+            resolve_frame(&mut rasterizer);
+
+            if take_screenshot {
+                match save_screenshot(&rasterizer) {
+                    Ok(file_path) => {
+                        println!("Saved screenshot to {}", file_path)
                     }
-                    rasterizer.draw_triangle(
-                        triangle_coords,
-                        tex_coords,
-                        mesh.material.diffuse_texture.as_ref().unwrap(),
-                    );
+                    Err(err) => println!("{:#}", err),
                 }
             }
 
+            // Report the culling statistics in the window title
+            if now.duration_since(last_stats_report).as_secs_f32() > 0.5 {
+                last_stats_report = now;
+                let stats = rasterizer.stats();
+                graphics_context.window().set_title(&format!(
+                    "soft-renderer - meshes: {} drawn, {} culled, {} \
+                     occluded - triangles: {} drawn, {} culled, {} occluded",
+                    stats.meshes_drawn,
+                    stats.meshes_culled,
+                    stats.meshes_occluded,
+                    stats.triangles_drawn,
+                    stats.triangles_culled,
+                    stats.triangles_occluded
+                ));
+            }
+
             graphics_context.set_buffer(
                 rasterizer.buffer(),
                 width as u16,
@@ -132,156 +601,369 @@ fn main() -> Result<()> {
     });
 }
 
-struct Model {
-    meshes: Vec<Mesh>,
+/// Saves the color and the depth buffer as PNG files with the first unused
+/// number. Returns the path of the color image.
+fn save_screenshot(rasterizer: &Rasterizer) -> Result<String> {
+    let index = (0..)
+        .find(|index| {
+            !std::path::Path::new(&screenshot_path(*index, "")).exists()
+        })
+        .unwrap();
+    let file_path = screenshot_path(index, "");
+    rasterizer.framebuffer().save_color(&file_path)?;
+    rasterizer.save_depth(&screenshot_path(index, "_depth"))?;
+    Ok(file_path)
 }
 
-impl Model {
-    pub fn new(meshes: Vec<Mesh>) -> Self {
-        Self { meshes }
-    }
+fn screenshot_path(index: u32, suffix: &str) -> String {
+    format!("{}_{:03}{}.png", SCREENSHOT_PREFIX, index, suffix)
 }
 
-struct Mesh {
-    vertices: Vec<Vertex>,
-    material: Material,
+/// Creates a rasterizer with the settings the viewer uses
+fn create_rasterizer() -> Rasterizer {
+    let mut rasterizer = Rasterizer::new();
+    rasterizer.set_origin(DrawOrigin::BottomLeft);
+    rasterizer.set_depth_state(DepthState::default());
+    rasterizer.set_depth_value(DepthState::default().clear_value());
+    rasterizer.set_hiz(true);
+    rasterizer.set_hdr_format(Some(ColorFormat::Rgba16F));
+    rasterizer
 }
 
-impl Mesh {
-    pub fn new(vertices: Vec<Vertex>, material: Material) -> Self {
-        Self { vertices, material }
-    }
+/// Number of point lights that circle the model in the point light modes
+const POINT_LIGHT_COUNT: usize = 32;
+
+/// Optional effects of the scene that can be toggled in the viewer
+#[derive(Default)]
+struct SceneSettings {
+    /// Draw an outline around the model using the stencil buffer
+    outline: bool,
+    /// Reflect the skybox in the materials of the non physically based
+    /// lighting modes
+    reflections: bool,
+    /// Turns the HDR target into the displayed colors
+    tone_mapping: ToneMapping,
+    bloom: bool,
+    vignette: bool,
+    /// Grade the colors with the lookup table of the scene
+    color_grade: bool,
+    fog: Option<FogMode>,
+    /// Thicken the fog towards the ground
+    height_fog: bool,
+    /// Occlude the ambient light with screen space ambient occlusion
+    ssao: bool,
+    lighting: LightingMode,
 }
 
-struct Material {
-    diffuse_color: Color,
-    diffuse_texture: Option<Texture>,
+/// How the model is lit
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+enum LightingMode {
+    /// A single animated directional light
+    #[default]
+    Directional,
+    /// Point and spot lights that are evaluated while rasterizing
+    Forward,
+    /// The same lights evaluated by the deferred renderer
+    Deferred,
+    /// Physically based shading lit by the environment
+    Pbr,
 }
 
-impl Material {
-    pub fn new(diffuse_color: Color, diffuse_texture: Option<Texture>) -> Self {
-        Self {
-            diffuse_color,
-            diffuse_texture,
+/// Returns the lights of the scene and the ambient light. `time` in seconds
+/// animates them.
+fn scene_lights(lighting: LightingMode, time: f32) -> (Vec<Light>, Vec3) {
+    match lighting {
+        LightingMode::Directional => {
+            let direction = Vec3::new(f32::sin(time * 2.0).abs(), 0.0, -1.0);
+            return (vec![Light::directional(direction)], Vec3::ZERO);
         }
+        LightingMode::Pbr => return (Vec::new(), Vec3::ZERO),
+        LightingMode::Forward | LightingMode::Deferred => {}
     }
+
+    // Colored point lights that circle the model at different heights
+    let mut lights: Vec<_> = (0..POINT_LIGHT_COUNT)
+        .map(|i| {
+            let t = i as f32 / POINT_LIGHT_COUNT as f32;
+            let angle = t * std::f32::consts::TAU + time * (0.5 + t);
+            let height = (t * 7.0).sin() * 0.8;
+            let position = Vec3::new(angle.cos(), height, angle.sin()) * 1.3;
+            let color = Vec3::new(
+                (t * 6.0).sin() * 0.5 + 0.5,
+                (t * 6.0 + 2.0).sin() * 0.5 + 0.5,
+                (t * 6.0 + 4.0).sin() * 0.5 + 0.5,
+            );
+            Light::point(position, 1.4)
+                .with_color(color)
+                .with_intensity(1.5)
+        })
+        .collect();
+
+    // A spot light in front of the model that sweeps from side to side
+    let target = Vec3::new((time * 0.7).sin() * 0.5, 0.0, 0.0);
+    let position = Vec3::new(0.0, 0.5, 2.5);
+    lights.push(
+        Light::spot(position, target - position, 5.0, 0.15, 0.25)
+            .with_intensity(1.5),
+    );
+
+    (lights, Vec3::splat(0.05))
 }
 
-impl Default for Material {
-    fn default() -> Self {
-        Self {
-            diffuse_color: (120, 120, 120),
-            diffuse_texture: None,
-        }
+/// Draws only the depth of the model and computes the ambient occlusion from
+/// it. Afterwards equal depths pass the depth test, so the model can be
+/// shaded on top of its depth.
+fn ambient_occlusion_prepass(
+    rasterizer: &mut Rasterizer,
+    camera: &Camera,
+    model: &Model,
+) -> AoMap {
+    // The prepass only fills the depth buffer and isn't counted
+    let stats = rasterizer.stats();
+    rasterizer.set_color_write(false);
+    for mesh in model.meshes() {
+        let shader =
+            TextureShader::new(camera.view_proj_mat(), mesh.material());
+        rasterizer.draw_mesh(mesh, &shader);
     }
+    rasterizer.set_color_write(true);
+    rasterizer.set_stats(stats);
+
+    let depth_state = rasterizer.depth_state();
+    let compare = if depth_state.compare.greater_wins() == Some(true) {
+        CompareFunc::GreaterEqual
+    } else {
+        CompareFunc::LessEqual
+    };
+    rasterizer.set_depth_state(DepthState {
+        compare,
+        ..depth_state
+    });
+    Ssao::new(16).compute(rasterizer, camera, None)
 }
 
-#[derive(Default, Copy, Clone, Debug)]
-struct Vertex {
-    position: Vec3,
-    tex_coord: Vec2,
-}
-impl Vertex {
-    pub fn new(position: Vec3, tex_coord: Vec2) -> Self {
-        Self {
-            position,
-            tex_coord,
-        }
+/// Returns the fog of the scene, which has the color of the horizon
+fn scene_fog(settings: &SceneSettings) -> Option<Fog> {
+    let mode = match (settings.fog, settings.height_fog) {
+        (Some(mode), _) => mode,
+        (None, true) => FogMode::Exp,
+        (None, false) => return None,
+    };
+    // Only the height fog if no mode is selected
+    let density = if settings.fog.is_some() { 0.2 } else { 0.0 };
+    let mut fog = Fog::new(mode, Vec3::new(0.75, 0.8, 0.85), density)
+        .with_range(2.0, 5.0);
+    if settings.height_fog {
+        fog = fog.with_height_fog(HeightFog {
+            density: 0.5,
+            base_height: -0.8,
+            falloff: 4.0,
+        });
     }
+    Some(fog)
 }
 
-fn load_obj(file_path: &str) -> Result<Model> {
-    let options = tobj::LoadOptions {
-        single_index: true,
-        triangulate: true,
-        ..Default::default()
+/// Clears the framebuffer and draws the model. `time` in seconds animates
+/// the lights.
+fn draw_scene(
+    rasterizer: &mut Rasterizer,
+    camera: &Camera,
+    scene: &Scene,
+    settings: &SceneSettings,
+    time: f32,
+) {
+    let (lights, ambient) = scene_lights(settings.lighting, time);
+    let view_proj_mat = camera.view_proj_mat();
+
+    rasterizer.clear();
+    rasterizer.set_frustum(Some(camera.frustum()));
+    rasterizer.set_fog(scene_fog(settings), camera.view_mat());
+    scene.skybox.draw(rasterizer, camera);
+
+    // Mark every pixel of the model in the stencil buffer, so the outline is
+    // only drawn around it
+    if settings.outline {
+        rasterizer.set_stencil_state(StencilState {
+            reference: 1,
+            pass: StencilOp::Replace,
+            ..StencilState::default()
+        });
+    }
+    let model = &scene.model;
+
+    // The forward modes need the depth before shading for the occlusion
+    let depth_state = rasterizer.depth_state();
+    let ao_map = if settings.ssao && settings.lighting != LightingMode::Deferred
+    {
+        Some(ambient_occlusion_prepass(rasterizer, camera, model))
+    } else {
+        None
     };
 
-    let (models, materials) = tobj::load_obj(file_path, &options)?;
-
-    let mut meshes = Vec::new();
-    for model in models {
-        let mesh = model.mesh;
-
-        let material = if let Some(material_id) = mesh.material_id {
-            let material = &materials.as_ref().unwrap()[material_id];
-            let diffuse_color = (
-                (material.diffuse[0] * 255.0) as u8,
-                (material.diffuse[1] * 255.0) as u8,
-                (material.diffuse[2] * 255.0) as u8,
-            );
-            let diffuse_tex_name = &material.diffuse_texture;
-            let diffuse_texture = if !material.diffuse_texture.is_empty() {
-                Some(Texture::from_file(diffuse_tex_name)?)
-            } else {
-                None
-            };
-
-            Material::new(diffuse_color, diffuse_texture)
-        } else {
-            // FIXME: This is only a temporary workaround beacuse there is no mtl file
-            Material::new(
-                (255, 255, 255),
-                Some(Texture::from_file(
-                    "assets/african_head/african_head_diffuse.tga",
-                )?),
-            )
-            // Material::default()
-        };
-
-        let mut vertices = Vec::new();
-        // Only triangles are supported
-        assert!(mesh.indices.len() % 3 == 0);
-
-        for face_idx in 0..(mesh.indices.len() / 3) {
-            let fv = 3; // Hardcode triangles
-            for i in 0..fv {
-                // Index
-                let idx = mesh.indices[fv * face_idx + i] as usize;
-
-                // Positions
-                let vx = mesh.positions[fv * idx + 0];
-                let vy = mesh.positions[fv * idx + 1];
-                let vz = mesh.positions[fv * idx + 2];
-
-                // Tex coords
-                let tu = mesh.texcoords[2 * idx + 0];
-                let tv = mesh.texcoords[2 * idx + 1];
-
-                vertices.push(Vertex::new(
-                    Vec3::new(vx, vy, vz),
-                    Vec2::new(tu, tv),
-                ));
+    match settings.lighting {
+        LightingMode::Directional | LightingMode::Forward => {
+            for mesh in model.meshes() {
+                let mut shader = LitShader::new(
+                    view_proj_mat,
+                    mesh.material(),
+                    &lights,
+                    ambient,
+                );
+                if settings.reflections {
+                    shader = shader.with_environment(
+                        scene.skybox.cubemap(),
+                        camera.position(),
+                    );
+                }
+                if let Some(ao_map) = &ao_map {
+                    shader = shader.with_ambient_occlusion(ao_map);
+                }
+                rasterizer.draw_mesh(mesh, &shader);
+            }
+        }
+        LightingMode::Deferred => {
+            let mut deferred_renderer = DeferredRenderer::new();
+            if settings.ssao {
+                deferred_renderer = deferred_renderer.with_ssao(Ssao::new(16));
+            }
+            if settings.reflections {
+                deferred_renderer =
+                    deferred_renderer.with_environment(scene.skybox.cubemap());
+            }
+            deferred_renderer.begin(rasterizer);
+            for mesh in model.meshes() {
+                deferred_renderer.draw_mesh(rasterizer, mesh, camera);
+            }
+            deferred_renderer.end(rasterizer, camera, &lights, ambient);
+        }
+        LightingMode::Pbr => {
+            for mesh in model.meshes() {
+                let mut shader = PbrShader::new(
+                    view_proj_mat,
+                    mesh.material(),
+                    camera.position(),
+                    &lights,
+                    ambient,
+                    Some(&scene.environment),
+                );
+                if let Some(ao_map) = &ao_map {
+                    shader = shader.with_ambient_occlusion(ao_map);
+                }
+                rasterizer.draw_mesh(mesh, &shader);
             }
         }
+    }
+    rasterizer.set_depth_state(depth_state);
+
+    if settings.outline {
+        let depth_state = rasterizer.depth_state();
+        rasterizer.set_stencil_state(StencilState {
+            compare: CompareFunc::NotEqual,
+            reference: 1,
+            ..StencilState::default()
+        });
+        rasterizer.set_depth_state(DepthState {
+            compare: CompareFunc::Always,
+            write: false,
+            ..depth_state
+        });
+        for mesh in scene.model.meshes() {
+            let shader = OutlineShader::new(view_proj_mat, 0.02, (255, 170, 0));
+            rasterizer.draw_mesh(mesh, &shader);
+        }
+        rasterizer.set_depth_state(depth_state);
+        rasterizer.set_stencil_state(StencilState::default());
+    }
 
-        meshes.push(Mesh::new(vertices, material))
+    // Effects on the HDR colors, the tone mapping and the grading of the
+    // displayed colors
+    let mut post_chain = PostChain::new();
+    if settings.bloom {
+        post_chain.push(Bloom::default());
+    }
+    if settings.vignette {
+        post_chain.push(Vignette::default());
     }
+    post_chain.push(settings.tone_mapping);
+    if let (true, Some(lut)) = (settings.color_grade, &scene.lut) {
+        post_chain.push(ColorGrade::new(lut));
+    }
+    post_chain.apply(rasterizer);
+}
 
-    Ok(Model::new(meshes))
+/// Writes the HDR target, which holds the displayed colors after the post
+/// processing of [`draw_scene`], to the color buffer
+fn resolve_frame(rasterizer: &mut Rasterizer) {
+    rasterizer.resolve_hdr(&ToneMapping {
+        operator: ToneMapOperator::Clamp,
+        exposure: 0.0,
+    });
 }
 
-/// Returns a matrix that projects coordinates from [-1, -1] to [x, y] and
-/// [1, 1] to [width, height]
-fn viewport(x: u32, y: u32, width: u32, height: u32, depth: u32) -> Mat4 {
-    let x = x as f32;
-    let y = y as f32;
-    let width = width as f32;
-    let height = height as f32;
-    let depth = depth as f32;
-
-    Mat4::from_cols(
-        Vec4::new(width / 2.0, 0.0, 0.0, x + width / 2.0),
-        Vec4::new(0.0, height / 2.0, 0.0, y + height / 2.0),
-        Vec4::new(0.0, 0.0, depth / 2.0, depth / 2.0),
-        Vec4::new(0.0, 0.0, 0.0, 1.0),
-    )
+/// Draws the frame statistics, the camera and the render mode in the top
+/// left corner. Has to be drawn before the frame is resolved.
+fn draw_overlay(
+    rasterizer: &mut Rasterizer,
+    camera: &Camera,
+    camera_mode: CameraMode,
+    settings: &SceneSettings,
+    frame_time: f32,
+) {
+    let stats = rasterizer.stats();
+    let position = camera.position();
+    let text = format!(
+        "{:.1} fps ({:.2} ms)\n\
+         triangles: {} drawn, {} culled, {} occluded\n\
+         camera: {:.2} {:.2} {:.2} ({:?})\n\
+         {}",
+        1.0 / frame_time.max(1e-6),
+        frame_time * 1000.0,
+        stats.triangles_drawn,
+        stats.triangles_culled,
+        stats.triangles_occluded,
+        position.x,
+        position.y,
+        position.z,
+        camera_mode,
+        render_mode(settings)
+    );
+
+    // The viewer draws from the bottom left, so the top is at the last row.
+    // A dark copy behind the text keeps it readable on bright backgrounds.
+    let top = rasterizer
+        .framebuffer()
+        .height()
+        .saturating_sub(OVERLAY_MARGIN);
+    rasterizer.draw_text(
+        (OVERLAY_MARGIN + 1, top.saturating_sub(1)),
+        &text,
+        (0, 0, 0),
+        1,
+    );
+    rasterizer.draw_text((OVERLAY_MARGIN, top), &text, (255, 255, 255), 1);
 }
 
-// fn m2v(m: Mat4) -> Vec3 {
-//     Vec3::new(
-//         m.x_axis[0] / m.w_axis[0],
-//         m.y_axis[0] / m.w_axis[0],
-//         m.z_axis[0] / m.w_axis[0],
-//     )
-// }
+/// Describes the lighting mode and the enabled effects
+fn render_mode(settings: &SceneSettings) -> String {
+    let mut mode = format!(
+        "{:?} lighting, {:?} {:+.1} EV",
+        settings.lighting,
+        settings.tone_mapping.operator,
+        settings.tone_mapping.exposure
+    );
+    let effects = [
+        (settings.outline, "outline"),
+        (settings.reflections, "reflections"),
+        (settings.bloom, "bloom"),
+        (settings.vignette, "vignette"),
+        (settings.color_grade, "grade"),
+        (settings.fog.is_some(), "fog"),
+        (settings.height_fog, "height fog"),
+        (settings.ssao, "ssao"),
+    ];
+    for (_, name) in effects.iter().filter(|(enabled, _)| *enabled) {
+        mode.push_str(", ");
+        mode.push_str(name);
+    }
+    mode
+}
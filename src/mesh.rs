@@ -0,0 +1,561 @@
+use crate::prelude::*;
+
+use std::collections::HashMap;
+
+/// Normals of faces that meet at a smaller angle than this are averaged when
+/// a mesh comes without normals. Larger angles produce a hard edge.
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::PI / 3.0;
+
+/// A model is a collection of meshes
+pub struct Model {
+    meshes: Vec<Mesh>,
+}
+
+impl Model {
+    pub fn new(meshes: Vec<Mesh>) -> Self {
+        Self { meshes }
+    }
+
+    /// Returns the meshes of the model
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    /// Moves the meshes of another model into this one
+    pub fn append(&mut self, mut other: Model) {
+        self.meshes.append(&mut other.meshes);
+    }
+}
+
+/// A mesh holds a vertex buffer and an index buffer. Every three indices
+/// describe one triangle.
+pub struct Mesh {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    material: Material,
+
+    aabb: Aabb,
+    bounding_sphere: BoundingSphere,
+}
+
+impl Mesh {
+    pub fn new(
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        material: Material,
+    ) -> Self {
+        assert!(indices.len().is_multiple_of(3));
+
+        let positions: Vec<Vec3> =
+            vertices.iter().map(|v| v.position).collect();
+        let aabb =
+            Aabb::from_points(positions.iter().copied()).unwrap_or(Aabb {
+                min: Vec3::ZERO,
+                max: Vec3::ZERO,
+            });
+        let bounding_sphere = BoundingSphere::from_points(&positions, &aabb);
+
+        Self {
+            vertices,
+            indices,
+            material,
+            aabb,
+            bounding_sphere,
+        }
+    }
+
+    /// Returns the vertex buffer
+    pub fn vertices(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    /// Returns the index buffer
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// Returns the material
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    /// Returns the number of triangles
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// Returns the bounding box of the vertex positions
+    pub fn aabb(&self) -> &Aabb {
+        &self.aabb
+    }
+
+    /// Returns the bounding sphere of the vertex positions
+    pub fn bounding_sphere(&self) -> &BoundingSphere {
+        &self.bounding_sphere
+    }
+}
+
+/// Surface description of a mesh. The physically based parameters follow the
+/// metallic-roughness model, their textures are multiplied with the factors.
+pub struct Material {
+    diffuse_color: Color,
+    diffuse_texture: Option<Texture>,
+    normal_texture: Option<Texture>,
+
+    metallic: f32,
+    roughness: f32,
+    /// Specular reflectance of dielectrics, 0.5 is a reflectance of 4%
+    specular: f32,
+    /// Roughness in the green and metalness in the blue channel
+    metallic_roughness_texture: Option<Texture>,
+    /// Grayscale glossiness, the inverse of roughness
+    gloss_texture: Option<Texture>,
+    /// Grayscale specular reflectance
+    specular_texture: Option<Texture>,
+    /// Fragments whose diffuse texture alpha is below this are discarded
+    alpha_cutoff: Option<f32>,
+    /// How much of the environment is mirrored by shaders without physically
+    /// based shading
+    reflectivity: f32,
+}
+
+impl Material {
+    pub fn new(diffuse_color: Color, diffuse_texture: Option<Texture>) -> Self {
+        Self {
+            diffuse_color,
+            diffuse_texture,
+            ..Self::default()
+        }
+    }
+
+    /// Sets a tangent space normal map
+    pub fn with_normal_texture(mut self, normal_texture: Texture) -> Self {
+        self.normal_texture = Some(normal_texture);
+        self
+    }
+
+    pub fn with_metallic_roughness(
+        mut self,
+        metallic: f32,
+        roughness: f32,
+    ) -> Self {
+        self.metallic = metallic;
+        self.roughness = roughness;
+        self
+    }
+
+    /// Sets a texture with roughness in the green and metalness in the blue
+    /// channel like in glTF
+    pub fn with_metallic_roughness_texture(mut self, texture: Texture) -> Self {
+        self.metallic_roughness_texture = Some(texture);
+        self
+    }
+
+    /// Sets a grayscale gloss map. The roughness is one minus the gloss.
+    pub fn with_gloss_texture(mut self, texture: Texture) -> Self {
+        self.gloss_texture = Some(texture);
+        self
+    }
+
+    pub fn with_specular(mut self, specular: f32) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    /// Sets a grayscale specular map
+    pub fn with_specular_texture(mut self, texture: Texture) -> Self {
+        self.specular_texture = Some(texture);
+        self
+    }
+
+    pub fn with_alpha_cutoff(mut self, alpha_cutoff: f32) -> Self {
+        self.alpha_cutoff = Some(alpha_cutoff);
+        self
+    }
+
+    /// Sets how much of the environment is mirrored by the [`LitShader`].
+    /// The specular map scales it.
+    pub fn with_reflectivity(mut self, reflectivity: f32) -> Self {
+        self.reflectivity = reflectivity;
+        self
+    }
+
+    pub fn diffuse_color(&self) -> Color {
+        self.diffuse_color
+    }
+
+    pub fn diffuse_texture(&self) -> Option<&Texture> {
+        self.diffuse_texture.as_ref()
+    }
+
+    pub fn normal_texture(&self) -> Option<&Texture> {
+        self.normal_texture.as_ref()
+    }
+
+    pub fn alpha_cutoff(&self) -> Option<f32> {
+        self.alpha_cutoff
+    }
+
+    /// Returns the metalness at the given texture coordinate
+    pub fn metallic(&self, tex_coord: Vec2) -> f32 {
+        match &self.metallic_roughness_texture {
+            Some(texture) => self.metallic * texture.rgba(tex_coord).z,
+            None => self.metallic,
+        }
+    }
+
+    /// Returns the perceptual roughness at the given texture coordinate
+    pub fn roughness(&self, tex_coord: Vec2) -> f32 {
+        let mut roughness = self.roughness;
+        if let Some(texture) = &self.metallic_roughness_texture {
+            roughness *= texture.rgba(tex_coord).y;
+        }
+        if let Some(texture) = &self.gloss_texture {
+            roughness *= 1.0 - texture.value(tex_coord);
+        }
+        roughness
+    }
+
+    /// Returns the specular reflectance at the given texture coordinate
+    pub fn specular(&self, tex_coord: Vec2) -> f32 {
+        match &self.specular_texture {
+            Some(texture) => self.specular * texture.value(tex_coord),
+            None => self.specular,
+        }
+    }
+
+    /// Returns the reflectivity at the given texture coordinate
+    pub fn reflectivity(&self, tex_coord: Vec2) -> f32 {
+        match &self.specular_texture {
+            Some(texture) => self.reflectivity * texture.value(tex_coord),
+            None => self.reflectivity,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            diffuse_color: (120, 120, 120),
+            diffuse_texture: None,
+            normal_texture: None,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            metallic_roughness_texture: None,
+            gloss_texture: None,
+            specular_texture: None,
+            alpha_cutoff: None,
+            reflectivity: 0.0,
+        }
+    }
+}
+
+#[derive(Default, Copy, Clone, Debug)]
+pub struct Vertex {
+    pub position: Vec3,
+    pub tex_coord: Vec2,
+    pub normal: Vec3,
+    /// Tangent in xyz and the sign of the bitangent in w. The bitangent is
+    /// `tangent.w * normal.cross(tangent.xyz)`.
+    pub tangent: Vec4,
+}
+
+impl Vertex {
+    pub fn new(position: Vec3, tex_coord: Vec2, normal: Vec3) -> Self {
+        Self {
+            position,
+            tex_coord,
+            normal,
+            tangent: Vec4::ZERO,
+        }
+    }
+}
+
+pub fn load_obj(file_path: &str) -> Result<Model> {
+    let options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+
+    let (models, materials) = tobj::load_obj(file_path, &options)?;
+
+    let mut meshes = Vec::new();
+    for model in models {
+        let mesh = model.mesh;
+
+        let material = if let Some(material_id) = mesh.material_id {
+            let material = &materials.as_ref().unwrap()[material_id];
+            let diffuse_color = (
+                (material.diffuse[0] * 255.0) as u8,
+                (material.diffuse[1] * 255.0) as u8,
+                (material.diffuse[2] * 255.0) as u8,
+            );
+            let diffuse_tex_name = &material.diffuse_texture;
+            let diffuse_texture = if !material.diffuse_texture.is_empty() {
+                Some(Texture::from_file(diffuse_tex_name)?.with_srgb(true))
+            } else {
+                None
+            };
+
+            Material::new(diffuse_color, diffuse_texture)
+        } else {
+            // FIXME: This is only a temporary workaround beacuse there is no mtl file
+            fallback_material(file_path)?
+        };
+
+        // Only triangles are supported
+        assert!(mesh.indices.len().is_multiple_of(3));
+
+        // With single_index every vertex owns one position and one texture
+        // coordinate, so the vertex buffer can be built directly
+        let vertex_count = mesh.positions.len() / 3;
+        let mut vertices = Vec::with_capacity(vertex_count);
+        for idx in 0..vertex_count {
+            // Positions
+            let vx = mesh.positions[3 * idx];
+            let vy = mesh.positions[3 * idx + 1];
+            let vz = mesh.positions[3 * idx + 2];
+
+            // Tex coords
+            let (tu, tv) = if mesh.texcoords.is_empty() {
+                (0.0, 0.0)
+            } else {
+                (mesh.texcoords[2 * idx], mesh.texcoords[2 * idx + 1])
+            };
+
+            // Normals
+            let normal = if mesh.normals.is_empty() {
+                Vec3::ZERO
+            } else {
+                Vec3::new(
+                    mesh.normals[3 * idx],
+                    mesh.normals[3 * idx + 1],
+                    mesh.normals[3 * idx + 2],
+                )
+            };
+
+            vertices.push(Vertex::new(
+                Vec3::new(vx, vy, vz),
+                Vec2::new(tu, tv),
+                normal,
+            ));
+        }
+
+        let mut indices = mesh.indices;
+        if mesh.normals.is_empty() {
+            (vertices, indices) =
+                generate_normals(&vertices, &indices, DEFAULT_CREASE_ANGLE);
+        }
+        if !mesh.texcoords.is_empty() {
+            (vertices, indices) = generate_tangents(&vertices, &indices);
+        }
+
+        meshes.push(Mesh::new(vertices, indices, material))
+    }
+
+    Ok(Model::new(meshes))
+}
+
+/// Builds a material from the textures next to an obj file without a mtl
+/// file. Textures are found by the naming of the african head assets, e.g.
+/// `<name>_diffuse.tga` and `<name>_nm_tangent.tga` for `<name>.obj`.
+fn fallback_material(file_path: &str) -> Result<Material> {
+    let stem = file_path.strip_suffix(".obj").unwrap_or(file_path);
+    let texture = |suffix: &str| -> Result<Option<Texture>> {
+        let texture_path = format!("{}_{}.tga", stem, suffix);
+        if std::path::Path::new(&texture_path).exists() {
+            Ok(Some(Texture::from_file(&texture_path)?))
+        } else {
+            Ok(None)
+        }
+    };
+
+    // Skin and eyes are dielectric, a gloss map makes the surface shiny
+    let diffuse_texture =
+        texture("diffuse")?.map(|texture| texture.with_srgb(true));
+    let mut material = Material::new((255, 255, 255), diffuse_texture)
+        .with_metallic_roughness(0.0, 0.6)
+        .with_alpha_cutoff(0.5)
+        .with_reflectivity(0.3);
+    if let Some(normal_texture) = texture("nm_tangent")? {
+        material = material.with_normal_texture(normal_texture);
+    }
+    if let Some(specular_texture) = texture("spec")? {
+        material = material.with_specular_texture(specular_texture);
+    }
+    if let Some(gloss_texture) = texture("gloss")? {
+        material = material
+            .with_metallic_roughness(0.0, 1.0)
+            .with_gloss_texture(gloss_texture);
+    }
+    Ok(material)
+}
+
+/// Computes angle weighted smooth normals for the given triangles. Faces that
+/// share a position contribute to each other's normals if the angle between
+/// them is at most `crease_angle` (in radians). Vertices that end up with
+/// different normals on different faces are split, so hard edges stay sharp.
+///
+/// Returns the new vertex and index buffer.
+pub fn generate_normals(
+    vertices: &[Vertex],
+    indices: &[u32],
+    crease_angle: f32,
+) -> (Vec<Vertex>, Vec<u32>) {
+    // Vertices are often already split by texture coordinates, so faces are
+    // connected through their positions instead of their indices
+    let mut position_ids = HashMap::new();
+    let corner_positions: Vec<usize> = indices
+        .iter()
+        .map(|&index| {
+            let p = vertices[index as usize].position;
+            let key = (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+            let next_id = position_ids.len();
+            *position_ids.entry(key).or_insert(next_id)
+        })
+        .collect();
+
+    // Unit normal of every face and the angle of each of its corners
+    let face_count = indices.len() / 3;
+    let mut face_normals = Vec::with_capacity(face_count);
+    let mut corner_angles = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let p = [0, 1, 2].map(|i| vertices[triangle[i] as usize].position);
+        face_normals.push((p[1] - p[0]).cross(p[2] - p[0]).normalize_or_zero());
+        for i in 0..3 {
+            let e0 = p[(i + 1) % 3] - p[i];
+            let e1 = p[(i + 2) % 3] - p[i];
+            corner_angles.push(e0.angle_between(e1));
+        }
+    }
+
+    // Corners that share a position
+    let mut position_corners = vec![Vec::new(); position_ids.len()];
+    for (corner, &position) in corner_positions.iter().enumerate() {
+        position_corners[position].push(corner);
+    }
+
+    let cos_crease = crease_angle.cos();
+    let mut new_vertices = Vec::new();
+    let mut new_indices = Vec::with_capacity(indices.len());
+    let mut vertex_ids = HashMap::new();
+    for (corner, &index) in indices.iter().enumerate() {
+        let face_normal = face_normals[corner / 3];
+
+        let mut normal = Vec3::ZERO;
+        for &other in &position_corners[corner_positions[corner]] {
+            let other_normal = face_normals[other / 3];
+            if face_normal.dot(other_normal) >= cos_crease {
+                normal += other_normal * corner_angles[other];
+            }
+        }
+        let normal = normal.try_normalize().unwrap_or(face_normal);
+
+        // Corners with the same source vertex and normal share a vertex
+        let key = (
+            index,
+            normal.x.to_bits(),
+            normal.y.to_bits(),
+            normal.z.to_bits(),
+        );
+        let new_index = *vertex_ids.entry(key).or_insert_with(|| {
+            let mut vertex = vertices[index as usize];
+            vertex.normal = normal;
+            new_vertices.push(vertex);
+            (new_vertices.len() - 1) as u32
+        });
+        new_indices.push(new_index);
+    }
+
+    (new_vertices, new_indices)
+}
+
+/// Generates per vertex tangents with MikkTSpace, the algorithm most normal
+/// map baking tools assume. The vertices need normals and texture
+/// coordinates. Vertices that get different tangents on different faces are
+/// split.
+///
+/// Returns the new vertex and index buffer.
+pub fn generate_tangents(
+    vertices: &[Vertex],
+    indices: &[u32],
+) -> (Vec<Vertex>, Vec<u32>) {
+    let mut geometry = TangentGeometry {
+        vertices,
+        indices,
+        tangents: vec![Vec4::ZERO; indices.len()],
+    };
+    if !bevy_mikktspace::generate_tangents(&mut geometry) {
+        return (vertices.to_vec(), indices.to_vec());
+    }
+
+    let mut new_vertices = Vec::new();
+    let mut new_indices = Vec::with_capacity(indices.len());
+    let mut vertex_ids = HashMap::new();
+    for (&index, tangent) in indices.iter().zip(geometry.tangents) {
+        // Corners with the same source vertex and tangent share a vertex
+        let key = (
+            index,
+            tangent.x.to_bits(),
+            tangent.y.to_bits(),
+            tangent.z.to_bits(),
+            tangent.w.to_bits(),
+        );
+        let new_index = *vertex_ids.entry(key).or_insert_with(|| {
+            let mut vertex = vertices[index as usize];
+            vertex.tangent = tangent;
+            new_vertices.push(vertex);
+            (new_vertices.len() - 1) as u32
+        });
+        new_indices.push(new_index);
+    }
+
+    (new_vertices, new_indices)
+}
+
+/// Exposes an indexed triangle list to MikkTSpace and collects the tangent
+/// of every face corner
+struct TangentGeometry<'a> {
+    vertices: &'a [Vertex],
+    indices: &'a [u32],
+    tangents: Vec<Vec4>,
+}
+
+impl<'a> TangentGeometry<'a> {
+    fn vertex(&self, face: usize, vert: usize) -> &Vertex {
+        &self.vertices[self.indices[face * 3 + vert] as usize]
+    }
+}
+
+impl<'a> bevy_mikktspace::Geometry for TangentGeometry<'a> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).position.to_array()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertex(face, vert).normal.to_array()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.vertex(face, vert).tex_coord.to_array()
+    }
+
+    fn set_tangent_encoded(
+        &mut self,
+        tangent: [f32; 4],
+        face: usize,
+        vert: usize,
+    ) {
+        self.tangents[face * 3 + vert] = Vec4::from_array(tangent);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::prelude::*;
+
+use std::f32::consts::PI;
+
+/// Surface parameters of the metallic-roughness model at a single point
+#[derive(Copy, Clone, Debug)]
+pub struct PbrSurface {
+    /// Linear base color
+    pub albedo: Vec3,
+    pub metallic: f32,
+    /// Perceptual roughness, squared before it is used as GGX alpha
+    pub roughness: f32,
+    /// Specular reflectance of dielectrics, 0.5 is a reflectance of 4%
+    pub specular: f32,
+    pub position: Vec3,
+    pub normal: Vec3,
+    /// Fraction of the indirect light that reaches the surface
+    pub occlusion: f32,
+}
+
+impl PbrSurface {
+    /// Returns the reflectance at normal incidence
+    pub fn f0(&self) -> Vec3 {
+        let dielectric = Vec3::splat(0.08 * self.specular);
+        dielectric.lerp(self.albedo, self.metallic)
+    }
+}
+
+/// Cook-Torrance shading with the GGX distribution, Smith-Schlick geometry
+/// term and Schlick Fresnel. `view` is the normalized direction from the
+/// surface to the camera.
+///
+/// Lights use the units of [`shade_lambert`], so a light with intensity 1
+/// makes a white rough dielectric look like with Lambert shading. Indirect
+/// light comes from the environment, or from the constant `ambient` if there
+/// is none.
+pub fn shade_pbr<'a>(
+    surface: &PbrSurface,
+    view: Vec3,
+    lights: impl IntoIterator<Item = &'a Light>,
+    ambient: Vec3,
+    environment: Option<&Environment>,
+) -> Vec3 {
+    let normal = surface.normal;
+    let n_dot_v = normal.dot(view).max(1e-4);
+    let roughness = surface.roughness.clamp(0.03, 1.0);
+    let alpha = roughness * roughness;
+    let f0 = surface.f0();
+    let diffuse_albedo = surface.albedo * (1.0 - surface.metallic);
+
+    let mut color = Vec3::ZERO;
+    for light in lights {
+        let Some((to_light, radiance)) = light.incident(surface.position)
+        else {
+            continue;
+        };
+        let n_dot_l = normal.dot(to_light);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        let half = (view + to_light).normalize_or_zero();
+        let n_dot_h = normal.dot(half).max(0.0);
+        let v_dot_h = view.dot(half).max(0.0);
+
+        let d = distribution_ggx(n_dot_h, alpha);
+        let k = (roughness + 1.0).powi(2) / 8.0;
+        let g = geometry_schlick(n_dot_v, k) * geometry_schlick(n_dot_l, k);
+        let f = fresnel_schlick(v_dot_h, f0);
+
+        let specular = d * g * f / (4.0 * n_dot_v * n_dot_l);
+        let diffuse = (Vec3::ONE - f) * diffuse_albedo / PI;
+        color += (diffuse + specular) * radiance * n_dot_l * PI;
+    }
+
+    // Split sum approximation for the indirect light
+    let f = f0
+        + (Vec3::splat(1.0 - roughness).max(f0) - f0) * (1.0 - n_dot_v).powi(5);
+    let diffuse_weight = (Vec3::ONE - f) * diffuse_albedo * surface.occlusion;
+    let specular_weight = surface.occlusion;
+    match environment {
+        Some(environment) => {
+            let reflection = 2.0 * n_dot_v * normal - view;
+            let brdf = environment.brdf(n_dot_v, roughness);
+            color += diffuse_weight * environment.irradiance(normal);
+            color += environment.specular(reflection, roughness)
+                * (f0 * brdf.x + brdf.y)
+                * specular_weight;
+        }
+        None => {
+            let brdf = env_brdf_approx(n_dot_v, roughness);
+            color += diffuse_weight * ambient;
+            color += ambient * (f0 * brdf.x + brdf.y) * specular_weight;
+        }
+    }
+    color
+}
+
+/// GGX normal distribution function
+pub(crate) fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha2 = alpha * alpha;
+    let denom = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denom * denom)
+}
+
+/// Schlick approximation of the Smith geometry term for one direction
+pub(crate) fn geometry_schlick(n_dot_x: f32, k: f32) -> f32 {
+    n_dot_x / (n_dot_x * (1.0 - k) + k)
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: Vec3) -> Vec3 {
+    f0 + (Vec3::ONE - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+/// Analytic fit of the split sum BRDF scale and bias for when there is no
+/// precomputed table
+fn env_brdf_approx(n_dot_v: f32, roughness: f32) -> Vec2 {
+    let c0 = Vec4::new(-1.0, -0.0275, -0.572, 0.022);
+    let c1 = Vec4::new(1.0, 0.0425, 1.04, -0.04);
+    let r = c0 * roughness + c1;
+    let a004 = (r.x * r.x).min((-9.28 * n_dot_v).exp2()) * r.x + r.y;
+    Vec2::new(a004 * -1.04 + r.z, a004 * 1.04 + r.w)
+}
@@ -0,0 +1,511 @@
+use crate::prelude::*;
+
+/// A linear color image that post-process passes read and write. Rows are in
+/// the order of the draw origin, like the positions of the rasterizer.
+#[derive(Clone, Default)]
+pub struct PostImage {
+    width: u32,
+    height: u32,
+    data: Vec<Vec3>,
+}
+
+impl PostImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![Vec3::ZERO; (width * height) as usize],
+        }
+    }
+
+    /// Changes the size, the content is undefined afterwards
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        self.data.resize((width * height) as usize, Vec3::ZERO);
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, pos: PixelPosition) -> Vec3 {
+        self.data[(pos.0 + pos.1 * self.width) as usize]
+    }
+
+    pub fn set(&mut self, pos: PixelPosition, color: Vec3) {
+        self.data[(pos.0 + pos.1 * self.width) as usize] = color;
+    }
+
+    /// Returns the pixel at the given signed position clamped to the edges
+    pub fn get_clamped(&self, x: i32, y: i32) -> Vec3 {
+        let x = x.clamp(0, self.width as i32 - 1) as u32;
+        let y = y.clamp(0, self.height as i32 - 1) as u32;
+        self.get((x, y))
+    }
+
+    /// Returns the bilinearly filtered color at a texture coordinate in
+    /// [0, 1]
+    pub fn sample(&self, tex_coord: Vec2) -> Vec3 {
+        let x = tex_coord.x * self.width as f32 - 0.5;
+        let y = tex_coord.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = self
+            .get_clamped(x0, y0)
+            .lerp(self.get_clamped(x0 + 1, y0), tx);
+        let bottom = self
+            .get_clamped(x0, y0 + 1)
+            .lerp(self.get_clamped(x0 + 1, y0 + 1), tx);
+        top.lerp(bottom, ty)
+    }
+
+    /// Returns an image with half the width and height by averaging 2x2
+    /// pixels. Empty images stay empty.
+    pub fn downsample(&self) -> Self {
+        let half = |size: u32| if size == 0 { 0 } else { (size / 2).max(1) };
+        let mut result = Self::new(half(self.width), half(self.height));
+        for y in 0..result.height {
+            for x in 0..result.width {
+                let (sx, sy) = (2 * x as i32, 2 * y as i32);
+                let sum = self.get_clamped(sx, sy)
+                    + self.get_clamped(sx + 1, sy)
+                    + self.get_clamped(sx, sy + 1)
+                    + self.get_clamped(sx + 1, sy + 1);
+                result.set((x, y), sum * 0.25);
+            }
+        }
+        result
+    }
+}
+
+/// What a post-process pass reads
+pub struct PostInput<'a> {
+    /// Linear color written by the previous pass or the scene
+    pub color: &'a PostImage,
+    /// Values of the depth buffer in the same layout as the color
+    pub depth: &'a [f32],
+}
+
+impl<'a> PostInput<'a> {
+    /// Returns the depth buffer value at the given position
+    pub fn depth(&self, pos: PixelPosition) -> f32 {
+        self.depth[(pos.0 + pos.1 * self.color.width()) as usize]
+    }
+}
+
+/// A full-screen effect that runs after the scene is drawn
+pub trait PostProcess {
+    /// Writes the new color of every pixel into `output`, which has the size
+    /// of the input color
+    fn apply(&self, input: &PostInput, output: &mut PostImage);
+}
+
+/// An ordered list of post-process passes. Every pass reads the color the
+/// previous one wrote.
+#[derive(Default)]
+pub struct PostChain<'a> {
+    passes: Vec<Box<dyn PostProcess + 'a>>,
+}
+
+impl<'a> PostChain<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a pass to the end of the chain
+    pub fn push(&mut self, pass: impl PostProcess + 'a) {
+        self.passes.push(Box::new(pass));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    /// Runs all passes on the color of the rasterizer and writes the result
+    /// back. With an HDR target the passes work on its values, which still
+    /// need to be resolved afterwards.
+    pub fn apply(&self, rasterizer: &mut Rasterizer) {
+        let width = rasterizer.framebuffer().width();
+        let height = rasterizer.framebuffer().height();
+        if self.passes.is_empty() || width == 0 || height == 0 {
+            return;
+        }
+        let mut color = PostImage::new(width, height);
+        let mut depth = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                color.set((x, y), rasterizer.pixel_linear((x, y)));
+                depth.push(rasterizer.depth((x, y)));
+            }
+        }
+
+        let mut output = PostImage::new(width, height);
+        for pass in &self.passes {
+            let input = PostInput {
+                color: &color,
+                depth: &depth,
+            };
+            pass.apply(&input, &mut output);
+            std::mem::swap(&mut color, &mut output);
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                rasterizer.set_pixel_linear((x, y), color.get((x, y)));
+            }
+        }
+    }
+}
+
+/// Blurs the image with a separable Gaussian kernel
+#[derive(Copy, Clone, Debug)]
+pub struct GaussianBlur {
+    /// Standard deviation in pixels
+    pub sigma: f32,
+}
+
+impl GaussianBlur {
+    pub fn new(sigma: f32) -> Self {
+        Self { sigma }
+    }
+
+    /// Blurs `image` in place, `scratch` holds the horizontal pass
+    pub fn blur(&self, image: &mut PostImage, scratch: &mut PostImage) {
+        let kernel = gaussian_kernel(self.sigma);
+        let radius = (kernel.len() / 2) as i32;
+        let (width, height) = (image.width(), image.height());
+        scratch.resize(width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Vec3::ZERO;
+                for (i, weight) in kernel.iter().enumerate() {
+                    let sx = x as i32 + i as i32 - radius;
+                    sum += image.get_clamped(sx, y as i32) * *weight;
+                }
+                scratch.set((x, y), sum);
+            }
+        }
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Vec3::ZERO;
+                for (i, weight) in kernel.iter().enumerate() {
+                    let sy = y as i32 + i as i32 - radius;
+                    sum += scratch.get_clamped(x as i32, sy) * *weight;
+                }
+                image.set((x, y), sum);
+            }
+        }
+    }
+}
+
+impl PostProcess for GaussianBlur {
+    fn apply(&self, input: &PostInput, output: &mut PostImage) {
+        output.clone_from(input.color);
+        self.blur(output, &mut PostImage::default());
+    }
+}
+
+/// Makes bright parts of the image glow. Colors above the threshold are
+/// blurred at half resolution and added back on top of the image.
+#[derive(Copy, Clone, Debug)]
+pub struct Bloom {
+    /// Luminance above which colors start to glow
+    pub threshold: f32,
+    /// Factor the glow is scaled with before it is added
+    pub intensity: f32,
+    /// Standard deviation of the blur in pixels of the full resolution
+    pub sigma: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.5,
+            sigma: 8.0,
+        }
+    }
+}
+
+impl PostProcess for Bloom {
+    fn apply(&self, input: &PostInput, output: &mut PostImage) {
+        let color = input.color;
+
+        // Bright pass, the part of the color above the threshold is kept
+        let mut bright = PostImage::new(color.width(), color.height());
+        for y in 0..color.height() {
+            for x in 0..color.width() {
+                let c = color.get((x, y));
+                let luminance = luminance(c);
+                let excess = (luminance - self.threshold).max(0.0);
+                bright.set((x, y), c * (excess / luminance.max(1e-4)));
+            }
+        }
+
+        let mut glow = bright.downsample();
+        GaussianBlur::new(self.sigma * 0.5)
+            .blur(&mut glow, &mut PostImage::default());
+
+        // Composite the upsampled glow
+        let size = Vec2::new(color.width() as f32, color.height() as f32);
+        for y in 0..color.height() {
+            for x in 0..color.width() {
+                let tex_coord = (Vec2::new(x as f32, y as f32) + 0.5) / size;
+                let c =
+                    color.get((x, y)) + glow.sample(tex_coord) * self.intensity;
+                output.set((x, y), c);
+            }
+        }
+    }
+}
+
+/// Darkens the image towards the corners
+#[derive(Copy, Clone, Debug)]
+pub struct Vignette {
+    /// How much the corners are darkened, 1 makes them black
+    pub strength: f32,
+    /// Distance from the center where the darkening starts and where it is
+    /// at full strength. 0 is the center and 1 a corner.
+    pub inner: f32,
+    pub outer: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            strength: 0.6,
+            inner: 0.4,
+            outer: 1.1,
+        }
+    }
+}
+
+impl PostProcess for Vignette {
+    fn apply(&self, input: &PostInput, output: &mut PostImage) {
+        let color = input.color;
+        let size = Vec2::new(color.width() as f32, color.height() as f32);
+        let half_diagonal = size.length() * 0.5;
+        for y in 0..color.height() {
+            for x in 0..color.width() {
+                let offset = Vec2::new(x as f32, y as f32) + 0.5 - size * 0.5;
+                let distance = offset.length() / half_diagonal;
+                let t = ((distance - self.inner)
+                    / (self.outer - self.inner).max(1e-4))
+                .clamp(0.0, 1.0);
+                let falloff = t * t * (3.0 - 2.0 * t);
+                let factor = 1.0 - self.strength * falloff;
+                output.set((x, y), color.get((x, y)) * factor);
+            }
+        }
+    }
+}
+
+/// Exposes and tone maps the image as a pass, so passes that expect
+/// display colors like [`ColorGrade`] can follow. The result stays linear.
+impl PostProcess for ToneMapping {
+    fn apply(&self, input: &PostInput, output: &mut PostImage) {
+        let color = input.color;
+        let scale = self.exposure.exp2();
+        for y in 0..color.height() {
+            for x in 0..color.width() {
+                let c = self.operator.apply(color.get((x, y)) * scale);
+                output.set((x, y), c);
+            }
+        }
+    }
+}
+
+/// Supported sizes of a [`Lut3d`] along every axis
+const LUT_SIZES: std::ops::RangeInclusive<u32> = 2..=256;
+
+/// A 3D color lookup table as stored in `.cube` files. The table maps sRGB
+/// encoded colors inside of the domain to new sRGB encoded colors.
+#[derive(Clone)]
+pub struct Lut3d {
+    size: u32,
+    /// Entries with red changing fastest, then green, then blue
+    data: Vec<Vec3>,
+    domain_min: Vec3,
+    domain_max: Vec3,
+}
+
+impl Lut3d {
+    /// A table that maps every color to itself. The size is clamped to
+    /// 2 to 256 entries per axis.
+    pub fn identity(size: u32) -> Self {
+        let size = size.clamp(*LUT_SIZES.start(), *LUT_SIZES.end());
+        let max = (size - 1) as f32;
+        let mut data = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push(Vec3::new(r as f32, g as f32, b as f32) / max);
+                }
+            }
+        }
+        Self {
+            size,
+            data,
+            domain_min: Vec3::ZERO,
+            domain_max: Vec3::ONE,
+        }
+    }
+
+    /// Loads a 3D table from a `.cube` file
+    pub fn from_file(file_path: &str) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(file_path)?)
+    }
+
+    /// Parses the content of a `.cube` file
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut size = None;
+        let mut data = Vec::new();
+        let mut domain_min = Vec3::ZERO;
+        let mut domain_max = Vec3::ONE;
+
+        let parse_vec3 = |values: &[&str]| -> Result<Vec3> {
+            if values.len() != 3 {
+                anyhow::bail!("Expected three values, found {:?}", values);
+            }
+            Ok(Vec3::new(
+                values[0].parse()?,
+                values[1].parse()?,
+                values[2].parse()?,
+            ))
+        };
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let keyword = parts.next().unwrap();
+            let values: Vec<_> = parts.collect();
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => size = Some(values.join("").parse::<u32>()?),
+                "LUT_1D_SIZE" => {
+                    anyhow::bail!("1D lookup tables are not supported")
+                }
+                "DOMAIN_MIN" => domain_min = parse_vec3(&values)?,
+                "DOMAIN_MAX" => domain_max = parse_vec3(&values)?,
+                _ => {
+                    let mut entry = vec![keyword];
+                    entry.extend(values);
+                    data.push(parse_vec3(&entry)?);
+                }
+            }
+        }
+
+        let Some(size) = size.filter(|size| LUT_SIZES.contains(size)) else {
+            anyhow::bail!("Missing or invalid LUT_3D_SIZE");
+        };
+        if data.len() != (size * size * size) as usize {
+            anyhow::bail!(
+                "Expected {} entries for a table of size {}, found {}",
+                size * size * size,
+                size,
+                data.len()
+            );
+        }
+        Ok(Self {
+            size,
+            data,
+            domain_min,
+            domain_max,
+        })
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Looks up an sRGB encoded color with trilinear interpolation. Colors
+    /// outside of the domain are clamped.
+    pub fn sample(&self, color: Vec3) -> Vec3 {
+        let max = (self.size - 1) as f32;
+        let range = (self.domain_max - self.domain_min).max(Vec3::splat(1e-6));
+        let coord = ((color - self.domain_min) / range)
+            .clamp(Vec3::ZERO, Vec3::ONE)
+            * max;
+        let base = coord.floor().min(Vec3::splat(max - 1.0));
+        let t = coord - base;
+        let (r, g, b) = (base.x as u32, base.y as u32, base.z as u32);
+
+        let entry = |r: u32, g: u32, b: u32| {
+            self.data[(r + (g + b * self.size) * self.size) as usize]
+        };
+        let lerp_r =
+            |g: u32, b: u32| entry(r, g, b).lerp(entry(r + 1, g, b), t.x);
+        let lower = lerp_r(g, b).lerp(lerp_r(g + 1, b), t.y);
+        let upper = lerp_r(g, b + 1).lerp(lerp_r(g + 1, b + 1), t.y);
+        lower.lerp(upper, t.z)
+    }
+}
+
+/// Grades the colors with a 3D lookup table. The table works on display
+/// colors, so in an HDR chain it has to come after the tone mapping.
+pub struct ColorGrade<'a> {
+    lut: &'a Lut3d,
+    /// Blend factor between the original and the graded color
+    strength: f32,
+}
+
+impl<'a> ColorGrade<'a> {
+    pub fn new(lut: &'a Lut3d) -> Self {
+        Self { lut, strength: 1.0 }
+    }
+
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+}
+
+impl<'a> PostProcess for ColorGrade<'a> {
+    fn apply(&self, input: &PostInput, output: &mut PostImage) {
+        let color = input.color;
+        for y in 0..color.height() {
+            for x in 0..color.width() {
+                let c = color.get((x, y)).clamp(Vec3::ZERO, Vec3::ONE);
+                let graded = srgb_to_linear(
+                    self.lut
+                        .sample(linear_to_srgb(c))
+                        .clamp(Vec3::ZERO, Vec3::ONE),
+                );
+                output.set((x, y), c.lerp(graded, self.strength));
+            }
+        }
+    }
+}
+
+/// Returns the relative luminance of a linear color
+pub fn luminance(color: Vec3) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Returns the normalized weights of a Gaussian kernel that reaches three
+/// standard deviations to each side
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    if sigma <= 0.0 {
+        return vec![1.0];
+    }
+    let radius = (sigma * 3.0).ceil() as i32;
+    let mut kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    for weight in &mut kernel {
+        *weight /= sum;
+    }
+    kernel
+}
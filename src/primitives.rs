@@ -0,0 +1,464 @@
+use crate::prelude::*;
+
+/// How the ends of a stroked line are drawn
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineCap {
+    /// The line ends exactly at the end points
+    #[default]
+    Butt,
+    /// The line is extended by half its width
+    Square,
+    /// A half circle is drawn around the end points
+    Round,
+}
+
+/// How the segments of a stroked polyline are connected
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineJoin {
+    /// The outer edges are extended until they meet, up to the miter limit
+    #[default]
+    Miter,
+    /// The outer corners are connected with a straight edge
+    Bevel,
+    /// A circle is drawn around the corner
+    Round,
+}
+
+/// Decides which parts of a self intersecting polygon are inside
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FillRule {
+    /// Inside if a ray crosses an odd number of edges
+    #[default]
+    EvenOdd,
+    /// Inside if the edges don't wind around the point zero times in sum
+    NonZero,
+}
+
+/// Describes how lines are stroked
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stroke {
+    /// Width of the line in pixels
+    pub width: f32,
+    pub cap: LineCap,
+    pub join: LineJoin,
+    /// Longest miter relative to half of the width, longer miters are
+    /// beveled
+    pub miter_limit: f32,
+}
+
+impl Default for Stroke {
+    fn default() -> Self {
+        Self {
+            width: 1.0,
+            cap: LineCap::Butt,
+            join: LineJoin::Miter,
+            miter_limit: 4.0,
+        }
+    }
+}
+
+impl Stroke {
+    pub fn new(width: f32) -> Self {
+        Self {
+            width,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_cap(mut self, cap: LineCap) -> Self {
+        self.cap = cap;
+        self
+    }
+
+    pub fn with_join(mut self, join: LineJoin) -> Self {
+        self.join = join;
+        self
+    }
+}
+
+/// 2D primitives for overlays and diagrams. Coordinates are in pixels
+/// relative to the draw origin, with the center of the pixel (x, y) at
+/// (x + 0.5, y + 0.5). Filled shapes cover the pixels whose center is
+/// inside of them and everything outside of the framebuffer or the scissor
+/// rectangle is skipped.
+impl Rasterizer {
+    /// Draws a one pixel wide anti-aliased line with Xiaolin Wu's algorithm.
+    /// The color is blended with the pixels by their coverage.
+    pub fn draw_line_aa(&mut self, start: Vec2, end: Vec2, color: Color) {
+        // Clip with a margin of a pixel, which the line may still partially
+        // cover
+        let bounds = self.draw_bounds();
+        if bounds.is_empty() {
+            return;
+        }
+        let min = Vec2::new(bounds.x as f32, bounds.y as f32) - 1.0;
+        let max = Vec2::new(
+            (bounds.x + bounds.width) as f32,
+            (bounds.y + bounds.height) as f32,
+        ) + 1.0;
+        let Some((start, end)) = clip_line(start, end, min, max) else {
+            return;
+        };
+
+        // The algorithm has pixel centers at integer coordinates
+        let (mut x0, mut y0) = (start.x - 0.5, start.y - 0.5);
+        let (mut x1, mut y1) = (end.x - 0.5, end.y - 0.5);
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        if steep {
+            std::mem::swap(&mut x0, &mut y0);
+            std::mem::swap(&mut x1, &mut y1);
+        }
+        if x0 > x1 {
+            std::mem::swap(&mut x0, &mut x1);
+            std::mem::swap(&mut y0, &mut y1);
+        }
+
+        let color = color_to_linear(color);
+        let mut plot = |x: f32, y: f32, coverage: f32| {
+            let (x, y) = if steep { (y, x) } else { (x, y) };
+            self.blend_pixel(x as i64, y as i64, color, coverage);
+        };
+        let dx = x1 - x0;
+        let gradient = if dx == 0.0 { 1.0 } else { (y1 - y0) / dx };
+
+        // End points, weighted by how much of their pixel the line covers
+        let x_start = x0.round();
+        let y_start = y0 + gradient * (x_start - x0);
+        let gap = 1.0 - fpart(x0 + 0.5);
+        plot(x_start, y_start.floor(), (1.0 - fpart(y_start)) * gap);
+        plot(x_start, y_start.floor() + 1.0, fpart(y_start) * gap);
+
+        let x_end = x1.round();
+        let y_end = y1 + gradient * (x_end - x1);
+        let gap = fpart(x1 + 0.5);
+        plot(x_end, y_end.floor(), (1.0 - fpart(y_end)) * gap);
+        plot(x_end, y_end.floor() + 1.0, fpart(y_end) * gap);
+
+        // Only the pixels between the end points that are inside of the
+        // bounds along the major axis
+        let (major_min, major_max) = if steep {
+            (bounds.y as f32, (bounds.y + bounds.height) as f32)
+        } else {
+            (bounds.x as f32, (bounds.x + bounds.width) as f32)
+        };
+        let first = (x_start + 1.0).max(major_min) as i64;
+        let last = x_end.min(major_max) as i64;
+        for x in first..last {
+            let x = x as f32;
+            let y = y_start + gradient * (x - x_start);
+            plot(x, y.floor(), 1.0 - fpart(y));
+            plot(x, y.floor() + 1.0, fpart(y));
+        }
+    }
+
+    /// Draws a line with the width and caps of the stroke
+    pub fn stroke_line(
+        &mut self,
+        start: Vec2,
+        end: Vec2,
+        stroke: &Stroke,
+        color: Color,
+    ) {
+        self.stroke_polyline(&[start, end], false, stroke, color);
+    }
+
+    /// Draws connected line segments through the points. Closed polylines
+    /// also connect the last point to the first one and are joined there
+    /// instead of capped.
+    pub fn stroke_polyline(
+        &mut self,
+        points: &[Vec2],
+        closed: bool,
+        stroke: &Stroke,
+        color: Color,
+    ) {
+        let half_width = stroke.width * 0.5;
+        let segment_count = if closed && points.len() > 2 {
+            points.len()
+        } else {
+            points.len().saturating_sub(1)
+        };
+
+        for i in 0..segment_count {
+            let mut start = points[i];
+            let mut end = points[(i + 1) % points.len()];
+            let Some(direction) = (end - start).try_normalize() else {
+                continue;
+            };
+            let normal = direction.perp() * half_width;
+            if !closed && stroke.cap == LineCap::Square {
+                if i == 0 {
+                    start -= direction * half_width;
+                }
+                if i + 1 == segment_count {
+                    end += direction * half_width;
+                }
+            }
+            self.fill_polygon(
+                &[start + normal, end + normal, end - normal, start - normal],
+                FillRule::NonZero,
+                color,
+            );
+        }
+
+        if !closed && stroke.cap == LineCap::Round {
+            if let (Some(&first), Some(&last)) = (points.first(), points.last())
+            {
+                self.fill_circle(first, half_width, color);
+                self.fill_circle(last, half_width, color);
+            }
+        }
+
+        // Joins at the inner points, or at every point of closed polylines
+        let joins = if closed && points.len() > 2 {
+            0..points.len()
+        } else {
+            1..points.len().saturating_sub(1)
+        };
+        for i in joins {
+            let point = points[i];
+            let previous = points[(i + points.len() - 1) % points.len()];
+            let next = points[(i + 1) % points.len()];
+            self.draw_join(previous, point, next, stroke, color);
+        }
+    }
+
+    /// Fills the gap at the outer side of the corner between two segments
+    fn draw_join(
+        &mut self,
+        previous: Vec2,
+        point: Vec2,
+        next: Vec2,
+        stroke: &Stroke,
+        color: Color,
+    ) {
+        let half_width = stroke.width * 0.5;
+        if stroke.join == LineJoin::Round {
+            self.fill_circle(point, half_width, color);
+            return;
+        }
+        let (Some(incoming), Some(outgoing)) = (
+            (point - previous).try_normalize(),
+            (next - point).try_normalize(),
+        ) else {
+            return;
+        };
+        let turn = incoming.perp_dot(outgoing);
+        if turn.abs() < 1e-6 {
+            return;
+        }
+
+        // The outer side is to the right of a left turn
+        let side = if turn > 0.0 { -1.0 } else { 1.0 };
+        let outer_in = point + incoming.perp() * half_width * side;
+        let outer_out = point + outgoing.perp() * half_width * side;
+        let miter_direction = (incoming.perp() + outgoing.perp()).normalize();
+        let miter_length = half_width / miter_direction.dot(incoming.perp());
+        if stroke.join == LineJoin::Miter
+            && miter_length.abs() <= stroke.miter_limit * half_width
+        {
+            let miter = point + miter_direction * miter_length * side;
+            self.fill_polygon(
+                &[point, outer_in, miter, outer_out],
+                FillRule::NonZero,
+                color,
+            );
+        } else {
+            self.fill_polygon(
+                &[point, outer_in, outer_out],
+                FillRule::NonZero,
+                color,
+            );
+        }
+    }
+
+    /// Draws the one pixel wide outline of the rectangle from `min` to
+    /// `min + size`
+    pub fn draw_rect(&mut self, min: Vec2, size: Vec2, color: Color) {
+        let max = min + size;
+        self.fill_rect(min, Vec2::new(size.x, 1.0), color);
+        self.fill_rect(
+            Vec2::new(min.x, max.y - 1.0),
+            Vec2::new(size.x, 1.0),
+            color,
+        );
+        self.fill_rect(min, Vec2::new(1.0, size.y), color);
+        self.fill_rect(
+            Vec2::new(max.x - 1.0, min.y),
+            Vec2::new(1.0, size.y),
+            color,
+        );
+    }
+
+    /// Fills the rectangle from `min` to `min + size`
+    pub fn fill_rect(&mut self, min: Vec2, size: Vec2, color: Color) {
+        let max = min + size;
+        for y in self.clamped_rows(pixel_start(min.y), pixel_start(max.y)) {
+            self.fill_span(y, pixel_start(min.x), pixel_start(max.x), color);
+        }
+    }
+
+    /// Draws the one pixel wide outline of a circle
+    pub fn draw_circle(&mut self, center: Vec2, radius: f32, color: Color) {
+        self.draw_ellipse(center, Vec2::splat(radius), color);
+    }
+
+    pub fn fill_circle(&mut self, center: Vec2, radius: f32, color: Color) {
+        self.fill_ellipse(center, Vec2::splat(radius), color);
+    }
+
+    /// Draws the one pixel wide outline of an axis aligned ellipse with the
+    /// given horizontal and vertical radius
+    pub fn draw_ellipse(&mut self, center: Vec2, radii: Vec2, color: Color) {
+        self.fill_ellipse_ring(center, radii + 0.5, radii - 0.5, color);
+    }
+
+    /// Fills an axis aligned ellipse with the given horizontal and vertical
+    /// radius
+    pub fn fill_ellipse(&mut self, center: Vec2, radii: Vec2, color: Color) {
+        self.fill_ellipse_ring(center, radii, Vec2::ZERO, color);
+    }
+
+    /// Fills the pixels inside of the outer ellipse that aren't inside of
+    /// the inner one
+    fn fill_ellipse_ring(
+        &mut self,
+        center: Vec2,
+        outer: Vec2,
+        inner: Vec2,
+        color: Color,
+    ) {
+        if outer.x <= 0.0 || outer.y <= 0.0 {
+            return;
+        }
+        // Half of the width of an ellipse at a vertical distance from the
+        // center, or `None` above or below it
+        let half_width = |radii: Vec2, dy: f32| {
+            if radii.x <= 0.0 || radii.y <= 0.0 || dy.abs() >= radii.y {
+                return None;
+            }
+            let t = dy / radii.y;
+            Some(radii.x * (1.0 - t * t).sqrt())
+        };
+
+        let y0 = pixel_start(center.y - outer.y);
+        let y1 = pixel_start(center.y + outer.y);
+        for y in self.clamped_rows(y0, y1) {
+            let dy = y as f32 + 0.5 - center.y;
+            let Some(outer_width) = half_width(outer, dy) else {
+                continue;
+            };
+            let x0 = pixel_start(center.x - outer_width);
+            let x1 = pixel_start(center.x + outer_width);
+            match half_width(inner, dy) {
+                Some(inner_width) => {
+                    let inner_x0 = pixel_start(center.x - inner_width);
+                    let inner_x1 = pixel_start(center.x + inner_width);
+                    self.fill_span(y, x0, inner_x0, color);
+                    self.fill_span(y, inner_x1.max(inner_x0), x1, color);
+                }
+                None => self.fill_span(y, x0, x1, color),
+            }
+        }
+    }
+
+    /// Fills a polygon, which may be concave or intersect itself. The last
+    /// point is connected to the first one.
+    pub fn fill_polygon(
+        &mut self,
+        points: &[Vec2],
+        fill_rule: FillRule,
+        color: Color,
+    ) {
+        if points.len() < 3 {
+            return;
+        }
+        let (min_y, max_y) = points
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), point| {
+                (min.min(point.y), max.max(point.y))
+            });
+
+        // Crossings of the edges with the center line of a row and the
+        // direction of the edge
+        let mut crossings: Vec<(f32, i32)> = Vec::new();
+        for y in self.clamped_rows(pixel_start(min_y), pixel_start(max_y)) {
+            let center = y as f32 + 0.5;
+            crossings.clear();
+            for (i, &a) in points.iter().enumerate() {
+                let b = points[(i + 1) % points.len()];
+                // Half open, so a vertex shared by two edges counts once
+                let winding = if a.y <= center && b.y > center {
+                    1
+                } else if b.y <= center && a.y > center {
+                    -1
+                } else {
+                    continue;
+                };
+                let t = (center - a.y) / (b.y - a.y);
+                crossings.push((a.x + (b.x - a.x) * t, winding));
+            }
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                let inside = match fill_rule {
+                    FillRule::EvenOdd => winding % 2 != 0,
+                    FillRule::NonZero => winding != 0,
+                };
+                if inside {
+                    let x0 = pixel_start(pair[0].0);
+                    let x1 = pixel_start(pair[1].0);
+                    self.fill_span(y, x0, x1, color);
+                }
+            }
+        }
+    }
+
+    /// Limits the rows from `y0` up to but excluding `y1` to the draw bounds,
+    /// so shapes far outside of them don't loop over every row
+    fn clamped_rows(&self, y0: i64, y1: i64) -> std::ops::Range<i64> {
+        let bounds = self.draw_bounds();
+        y0.max(bounds.y as i64)..y1.min((bounds.y + bounds.height) as i64)
+    }
+
+    /// Sets the pixels of a row from `x0` up to but excluding `x1`
+    fn fill_span(&mut self, y: i64, x0: i64, x1: i64, color: Color) {
+        let bounds = self.draw_bounds();
+        if y < bounds.y as i64 || y >= (bounds.y + bounds.height) as i64 {
+            return;
+        }
+        let (left, right) = (bounds.x as i64, (bounds.x + bounds.width) as i64);
+        for x in x0.max(left)..x1.min(right) {
+            self.set_pixel((x as u32, y as u32), color);
+        }
+    }
+
+    /// Blends a linear color over a pixel by the given coverage
+    fn blend_pixel(&mut self, x: i64, y: i64, color: Vec3, coverage: f32) {
+        let bounds = self.draw_bounds();
+        if x < bounds.x as i64
+            || y < bounds.y as i64
+            || x >= (bounds.x + bounds.width) as i64
+            || y >= (bounds.y + bounds.height) as i64
+            || coverage <= 0.0
+        {
+            return;
+        }
+        let pos = (x as u32, y as u32);
+        let blended = self.pixel_linear(pos).lerp(color, coverage.min(1.0));
+        self.set_pixel_linear(pos, blended);
+    }
+}
+
+/// Returns the fractional part of a coordinate towards negative infinity,
+/// unlike `f32::fract` it is also in [0, 1) for negative values
+fn fpart(x: f32) -> f32 {
+    x - x.floor()
+}
+
+/// Returns the first pixel whose center is at or after the coordinate
+fn pixel_start(coordinate: f32) -> i64 {
+    (coordinate - 0.5).ceil() as i64
+}
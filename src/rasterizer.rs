@@ -5,7 +5,80 @@ pub struct Rasterizer {
     framebuffer: Framebuffer,
     clear_color: Color,
     depth_value: f32,
+    depth_state: DepthState,
+    stencil_value: u8,
+    stencil_state: StencilState,
+    color_write: bool,
     draw_origin: DrawOrigin,
+    scissor: Option<ScissorRect>,
+    vertex_cache: VertexCache,
+    /// Scratch buffer for the color attachment outputs of a fragment
+    fragment_outputs: Vec<Vec4>,
+    frustum: Option<Frustum>,
+    depth_pyramid: Option<DepthPyramid>,
+    fog: Option<Fog>,
+    /// View matrix the fog depth is computed with
+    fog_view: Mat4,
+    fog_camera_position: Vec3,
+    stats: RenderStats,
+}
+
+/// Counts what happened to the meshes and triangles since the last clear
+#[derive(Default, Copy, Clone, Debug)]
+pub struct RenderStats {
+    pub meshes_drawn: u32,
+    /// Meshes that were rejected by their bounding volumes
+    pub meshes_culled: u32,
+    pub triangles_drawn: u32,
+    /// Triangles that were rejected, including those of culled meshes
+    pub triangles_culled: u32,
+    /// Meshes that were hidden behind already drawn geometry
+    pub meshes_occluded: u32,
+    /// Triangles that were hidden behind already drawn geometry, including
+    /// those of occluded meshes
+    pub triangles_occluded: u32,
+    /// Tiles of triangles that were skipped because they were hidden
+    pub tiles_occluded: u32,
+}
+
+/// Rectangle in pixels relative to the draw origin that drawing is limited
+/// to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ScissorRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScissorRect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns the overlap of two rectangles, which may be empty
+    pub fn intersect(&self, other: &ScissorRect) -> ScissorRect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self
+            .x
+            .saturating_add(self.width)
+            .min(other.x.saturating_add(other.width));
+        let top = self
+            .y
+            .saturating_add(self.height)
+            .min(other.y.saturating_add(other.height));
+        Self::new(x, y, right.saturating_sub(x), top.saturating_sub(y))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
 }
 
 /// Origin for drawing operations
@@ -14,14 +87,33 @@ pub enum DrawOrigin {
     BottomLeft,
 }
 
+impl Default for Rasterizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Rasterizer {
     /// Create a new Rasterizer
     pub fn new() -> Self {
         Self {
             framebuffer: Framebuffer::new(0, 0),
             clear_color: (0, 0, 0),
-            depth_value: 0.0,
+            depth_value: 1.0,
+            depth_state: DepthState::default(),
+            stencil_value: 0,
+            stencil_state: StencilState::default(),
+            color_write: true,
             draw_origin: DrawOrigin::TopLeft,
+            scissor: None,
+            vertex_cache: VertexCache::new(),
+            fragment_outputs: Vec::new(),
+            frustum: None,
+            depth_pyramid: None,
+            fog: None,
+            fog_view: Mat4::IDENTITY,
+            fog_camera_position: Vec3::ZERO,
+            stats: RenderStats::default(),
         }
     }
 
@@ -30,6 +122,32 @@ impl Rasterizer {
         self.draw_origin = origin;
     }
 
+    /// Limits lines, triangles, text and the 2D primitives to a rectangle.
+    /// Clearing, the skybox and post processing always cover the whole
+    /// framebuffer.
+    pub fn set_scissor(&mut self, scissor: Option<ScissorRect>) {
+        self.scissor = scissor;
+    }
+
+    pub fn scissor(&self) -> Option<ScissorRect> {
+        self.scissor
+    }
+
+    /// Returns the part of the framebuffer that can be drawn to, limited by
+    /// the scissor rectangle
+    pub fn draw_bounds(&self) -> ScissorRect {
+        let framebuffer = ScissorRect::new(
+            0,
+            0,
+            self.framebuffer.width(),
+            self.framebuffer.height(),
+        );
+        match &self.scissor {
+            Some(scissor) => framebuffer.intersect(scissor),
+            None => framebuffer,
+        }
+    }
+
     /// Resizes the framebuffer if the width and height does not match
     pub fn resize(&mut self, width: u16, height: u16) {
         self.framebuffer.resize(width as u32, height as u32);
@@ -45,13 +163,164 @@ impl Rasterizer {
         self.depth_value = depth;
     }
 
-    /// Clears the background to the clear color
+    /// Set how fragments are tested against and written to the depth buffer
+    pub fn set_depth_state(&mut self, depth_state: DepthState) {
+        self.depth_state = depth_state;
+    }
+
+    pub fn depth_state(&self) -> DepthState {
+        self.depth_state
+    }
+
+    /// Set how the depth buffer stores depth values. The depth buffer has to
+    /// be cleared afterwards.
+    pub fn set_depth_format(&mut self, format: DepthFormat) {
+        self.framebuffer.set_depth_format(format);
+    }
+
+    pub fn depth_format(&self) -> DepthFormat {
+        self.framebuffer.depth_format()
+    }
+
+    /// Set the stencil value that is used
+    pub fn set_stencil_value(&mut self, value: u8) {
+        self.stencil_value = value;
+    }
+
+    /// Set how fragments are tested against and written to the stencil
+    /// buffer. The stencil test runs before the depth test.
+    pub fn set_stencil_state(&mut self, stencil_state: StencilState) {
+        self.stencil_state = stencil_state;
+    }
+
+    pub fn stencil_state(&self) -> StencilState {
+        self.stencil_state
+    }
+
+    /// Enables or disables writing fragment colors. With color writes
+    /// disabled only the depth and stencil buffer are updated.
+    pub fn set_color_write(&mut self, enabled: bool) {
+        self.color_write = enabled;
+    }
+
+    pub fn color_write(&self) -> bool {
+        self.color_write
+    }
+
+    /// Adds a color attachment to the framebuffer and returns its index.
+    /// Fragment shaders write to it with [`Shader::fragment_outputs`].
+    pub fn add_color_attachment(
+        &mut self,
+        format: ColorFormat,
+        clear_value: Vec4,
+    ) -> usize {
+        self.framebuffer.add_color_attachment(format, clear_value)
+    }
+
+    pub fn remove_color_attachments(&mut self) {
+        self.framebuffer.remove_color_attachments();
+    }
+
+    /// Set the view frustum. Meshes whose bounding volumes are completely
+    /// outside of it are not drawn. The bounding volumes are in world space,
+    /// so the frustum has to be extracted from the view projection matrix.
+    pub fn set_frustum(&mut self, frustum: Option<Frustum>) {
+        self.frustum = frustum;
+    }
+
+    /// Enables hierarchical-Z occlusion culling. A min/max depth pyramid is
+    /// kept next to the depth buffer, so meshes, triangles and tiles of
+    /// triangles that are hidden behind already drawn geometry are rejected
+    /// before any per pixel work. Mesh rejection needs a frustum. Culling
+    /// only happens while the depth compare function prefers a direction and
+    /// the stencil state doesn't change the stencil buffer on failed tests.
+    pub fn set_hiz(&mut self, enabled: bool) {
+        self.depth_pyramid = if enabled {
+            let mut depth_pyramid = DepthPyramid::new();
+            depth_pyramid.build(&self.framebuffer);
+            Some(depth_pyramid)
+        } else {
+            None
+        };
+    }
+
+    pub fn hiz(&self) -> bool {
+        self.depth_pyramid.is_some()
+    }
+
+    /// Blends every fragment towards the fog color by its depth in the view
+    /// space of `view_mat`. Has to be updated when the camera moves.
+    pub fn set_fog(&mut self, fog: Option<Fog>, view_mat: Mat4) {
+        self.fog = fog;
+        self.fog_view = view_mat;
+        self.fog_camera_position = view_mat.inverse().w_axis.truncate();
+    }
+
+    pub fn fog(&self) -> Option<Fog> {
+        self.fog
+    }
+
+    /// Applies the fog to the linear color of a surface at the given world
+    /// space position. Returns the color unchanged without fog.
+    pub fn apply_fog(&self, color: Vec3, position: Vec3) -> Vec3 {
+        match &self.fog {
+            Some(fog) => {
+                let depth = -self.fog_view.transform_point3(position).z;
+                fog.apply(color, depth, self.fog_camera_position, position)
+            }
+            None => color,
+        }
+    }
+
+    /// Draws into a floating point HDR target with the given format instead
+    /// of the color buffer, or directly into the color buffer with `None`.
+    /// The HDR target is written to the color buffer by
+    /// [`Rasterizer::resolve_hdr`].
+    pub fn set_hdr_format(&mut self, format: Option<ColorFormat>) {
+        self.framebuffer.set_hdr_format(format);
+    }
+
+    pub fn hdr_format(&self) -> Option<ColorFormat> {
+        self.framebuffer.hdr_format()
+    }
+
+    /// Tone maps the HDR target into the color buffer. Does nothing without
+    /// an HDR target.
+    pub fn resolve_hdr(&mut self, tone_mapping: &ToneMapping) {
+        self.framebuffer.resolve_hdr(tone_mapping);
+    }
+
+    /// Clears the background to the clear color and resets the statistics
     pub fn clear(&mut self) {
         self.framebuffer.set_color_rgb_all(self.clear_color);
+        self.framebuffer
+            .set_hdr_all(color_to_linear(self.clear_color));
         self.framebuffer.set_depth_all(self.depth_value);
+        self.framebuffer.set_stencil_all(self.stencil_value);
+        self.framebuffer.clear_color_attachments();
+        if let Some(depth_pyramid) = &mut self.depth_pyramid {
+            depth_pyramid.reset(
+                self.framebuffer.width(),
+                self.framebuffer.height(),
+                self.depth_value,
+            );
+        }
+        self.stats = RenderStats::default();
     }
 
-    /// Draws a line with the given color
+    /// Returns the statistics since the last clear
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    /// Replaces the statistics, e.g. to leave out the draws of a prepass
+    pub fn set_stats(&mut self, stats: RenderStats) {
+        self.stats = stats;
+    }
+
+    /// Draws a line with the given color. The line is clipped to the
+    /// framebuffer and the scissor rectangle, so the end points may be
+    /// outside of them.
     pub fn draw_line(
         &mut self,
         start: PixelPosition,
@@ -60,11 +329,27 @@ impl Rasterizer {
     ) {
         use std::mem::swap;
 
-        let mut x0 = start.0 as i32;
-        let mut y0 = start.1 as i32;
+        let bounds = self.draw_bounds();
+        if bounds.is_empty() {
+            return;
+        }
+        let min = Vec2::new(bounds.x as f32, bounds.y as f32);
+        let max = min
+            + Vec2::new((bounds.width - 1) as f32, (bounds.height - 1) as f32);
+        let Some((start, end)) = clip_line(
+            Vec2::new(start.0 as f32, start.1 as f32),
+            Vec2::new(end.0 as f32, end.1 as f32),
+            min,
+            max,
+        ) else {
+            return;
+        };
+
+        let mut x0 = start.x.round() as i32;
+        let mut y0 = start.y.round() as i32;
 
-        let mut x1 = end.0 as i32;
-        let mut y1 = end.1 as i32;
+        let mut x1 = end.x.round() as i32;
+        let mut y1 = end.y.round() as i32;
 
         let steep = if (x0 - x1).abs() < (y0 - y1).abs() {
             swap(&mut x0, &mut y0);
@@ -98,100 +383,648 @@ impl Rasterizer {
         }
     }
 
-    /// Draw a triangle between the given points with the given color
-    pub fn draw_triangle(
+    /// Draws text with the embedded font. `pos` is the top left corner of
+    /// the first line relative to the draw origin and the text is always
+    /// drawn upright. Every font pixel covers `scale` by `scale` pixels and
+    /// pixels outside of the framebuffer or the scissor rectangle are
+    /// skipped.
+    pub fn draw_text(
+        &mut self,
+        pos: PixelPosition,
+        text: &str,
+        color: Color,
+        scale: u32,
+    ) {
+        let bounds = self.draw_bounds();
+        let (left, right) = (bounds.x as i64, (bounds.x + bounds.width) as i64);
+        let (bottom, top) =
+            (bounds.y as i64, (bounds.y + bounds.height) as i64);
+        // Rows go down the screen, which is towards smaller Y with the
+        // origin at the bottom
+        let down = match self.draw_origin {
+            DrawOrigin::TopLeft => 1,
+            DrawOrigin::BottomLeft => -1,
+        };
+        let scale = scale.max(1) as i64;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let line_y = line_index as i64 * (LINE_HEIGHT as i64) * scale;
+            for (char_index, c) in line.chars().enumerate() {
+                let char_x = char_index as i64 * (GLYPH_ADVANCE as i64) * scale;
+                for (column, bits) in glyph(c).into_iter().enumerate() {
+                    for row in 0..GLYPH_HEIGHT as i64 {
+                        if bits & (1 << row) == 0 {
+                            continue;
+                        }
+                        for sy in 0..scale {
+                            for sx in 0..scale {
+                                let x = pos.0 as i64
+                                    + char_x
+                                    + column as i64 * scale
+                                    + sx;
+                                let y = pos.1 as i64
+                                    + (line_y + row * scale + sy) * down;
+                                if x >= left
+                                    && y >= bottom
+                                    && x < right
+                                    && y < top
+                                {
+                                    self.set_pixel((x as u32, y as u32), color);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws an indexed mesh with the given shader. The results of the vertex
+    /// shader are cached by index, so vertices that are shared between
+    /// triangles are only transformed once.
+    pub fn draw_mesh<S: Shader>(&mut self, mesh: &Mesh, shader: &S) {
+        if let Some(frustum) = &self.frustum {
+            if !frustum.intersects_mesh(mesh) {
+                self.stats.meshes_culled += 1;
+                self.stats.triangles_culled += mesh.triangle_count() as u32;
+                return;
+            }
+        }
+        if self.is_mesh_occluded(mesh) {
+            self.stats.meshes_occluded += 1;
+            self.stats.triangles_occluded += mesh.triangle_count() as u32;
+            return;
+        }
+        self.stats.meshes_drawn += 1;
+
+        let vertices = mesh.vertices();
+        self.vertex_cache.reset(vertices.len());
+
+        for triangle in mesh.indices().chunks_exact(3) {
+            let mut outputs = [VertexOutput::default(); 3];
+            for (output, &index) in outputs.iter_mut().zip(triangle) {
+                *output = self.vertex_cache.get_or_insert_with(index, || {
+                    shader.vertex(&vertices[index as usize])
+                });
+            }
+            self.draw_triangle(outputs, shader);
+        }
+
+        if let Some(depth_pyramid) = &mut self.depth_pyramid {
+            depth_pyramid.update(&self.framebuffer);
+        }
+    }
+
+    /// Tests the bounding box of the mesh against the depth pyramid
+    fn is_mesh_occluded(&self, mesh: &Mesh) -> bool {
+        let (Some(depth_pyramid), Some(frustum)) =
+            (&self.depth_pyramid, &self.frustum)
+        else {
+            return false;
+        };
+
+        let view_proj = frustum.view_proj();
+        let corners = mesh
+            .aabb()
+            .corners()
+            .map(|corner| view_proj * corner.extend(1.0));
+        self.is_occluded(depth_pyramid, &corners)
+    }
+
+    /// Tests the screen space bounding rectangle of the clip space positions
+    /// against the depth pyramid
+    fn is_occluded(
+        &self,
+        depth_pyramid: &DepthPyramid,
+        positions: &[Vec4],
+    ) -> bool {
+        let Some(greater_wins) = self.occlusion_culling() else {
+            return false;
+        };
+        let Some((min, max, z_min, z_max)) = self.screen_bounds(positions)
+        else {
+            return false;
+        };
+
+        // Compare in the precision of the depth buffer, so that fragments
+        // that would end up equal to the stored depth aren't rejected
+        let format = self.framebuffer.depth_format();
+        let closest = if greater_wins {
+            format.quantize(z_max)
+        } else {
+            format.quantize(z_min)
+        };
+        depth_pyramid.is_occluded(min, max, closest, greater_wins)
+    }
+
+    /// Returns whether larger depth values are closer if hidden geometry may
+    /// be skipped, `None` otherwise
+    fn occlusion_culling(&self) -> Option<bool> {
+        if self.stencil_state.writes_on_fail() {
+            return None;
+        }
+        self.depth_state.compare.greater_wins()
+    }
+
+    /// Returns the pixel rectangle and the depth range of the given clip
+    /// space positions. Returns `None` if a position is behind the near plane
+    /// or the rectangle is outside of the framebuffer.
+    fn screen_bounds(
+        &self,
+        positions: &[Vec4],
+    ) -> Option<(UVec2, UVec2, f32, f32)> {
+        let mut min = Vec2::splat(f32::MAX);
+        let mut max = Vec2::splat(f32::MIN);
+        let mut z_min = f32::MAX;
+        let mut z_max = f32::MIN;
+        for &position in positions {
+            if self.near_distance(position) < 0.0 {
+                return None;
+            }
+            let screen = self.viewport_transform(position);
+            min = min.min(screen.truncate());
+            max = max.max(screen.truncate());
+            z_min = z_min.min(screen.z);
+            z_max = z_max.max(screen.z);
+        }
+
+        let size = Vec2::new(
+            self.framebuffer.width() as f32,
+            self.framebuffer.height() as f32,
+        );
+        if max.x < 0.0 || max.y < 0.0 || min.x >= size.x || min.y >= size.y {
+            return None;
+        }
+        let min = min.max(Vec2::ZERO).as_uvec2();
+        let max = max.min(size - 1.0).as_uvec2();
+        Some((min, max, z_min, z_max))
+    }
+
+    /// Perspective divide and viewport transform. Depth is mapped from
+    /// [-1, 1] to [0, 1]. With reversed Z the clip space depth already is in
+    /// [1, 0] and is kept as it is.
+    fn viewport_transform(&self, position: Vec4) -> Vec3 {
+        let width = self.framebuffer.width() as f32;
+        let height = self.framebuffer.height() as f32;
+        let ndc = position.truncate() / position.w;
+        let depth = if self.depth_state.reversed_z {
+            ndc.z
+        } else {
+            (ndc.z + 1.0) * 0.5
+        };
+        Vec3::new(
+            (ndc.x + 1.0) * 0.5 * width,
+            (ndc.y + 1.0) * 0.5 * height,
+            depth,
+        )
+    }
+
+    /// Signed distance of a clip space position to the near plane, positive
+    /// in front of it
+    fn near_distance(&self, position: Vec4) -> f32 {
+        if self.depth_state.reversed_z {
+            position.w - position.z
+        } else {
+            position.z + position.w
+        }
+    }
+
+    /// Signed distance of a clip space position to the far plane, positive
+    /// in front of it
+    fn far_distance(&self, position: Vec4) -> f32 {
+        if self.depth_state.reversed_z {
+            position.z
+        } else {
+            position.w - position.z
+        }
+    }
+
+    /// Draw a triangle between the given vertices and shade it with the given
+    /// shader. The vertex positions are in clip space. Triangles are clipped
+    /// against the near plane before they are rasterized.
+    pub fn draw_triangle<S: Shader>(
         &mut self,
-        positions: [Vec3; 3],
-        tex_coords: [Vec2; 3],
-        texture: &Texture,
+        vertices: [VertexOutput; 3],
+        shader: &S,
     ) {
+        // Reject the triangle if all vertices are outside of the same plane
+        // of the view volume
+        let outside = |plane: &dyn Fn(Vec4) -> f32| {
+            vertices.iter().all(|v| plane(v.position) < 0.0)
+        };
+        let mut culled = outside(&|p| self.near_distance(p))
+            || outside(&|p| self.far_distance(p));
+        for axis in 0..2 {
+            culled |=
+                outside(&|p| p.w - p[axis]) || outside(&|p| p.w + p[axis]);
+        }
+        if culled {
+            self.stats.triangles_culled += 1;
+            return;
+        }
+
+        // Reject the triangle if it is hidden behind already drawn geometry
+        if let Some(depth_pyramid) = &self.depth_pyramid {
+            let positions = vertices.map(|v| v.position);
+            if self.is_occluded(depth_pyramid, &positions) {
+                self.stats.triangles_occluded += 1;
+                return;
+            }
+        }
+        self.stats.triangles_drawn += 1;
+
+        let polygon = clip_near(&vertices, |p| self.near_distance(p));
+        for i in 1..polygon.len().saturating_sub(1) {
+            self.rasterize_triangle(
+                [polygon[0], polygon[i], polygon[i + 1]],
+                shader,
+            );
+        }
+    }
+
+    /// Rasterizes a triangle that lies completely in front of the near plane
+    fn rasterize_triangle<S: Shader>(
+        &mut self,
+        vertices: [VertexOutput; 3],
+        shader: &S,
+    ) {
+        let bounds = self.draw_bounds();
+        if bounds.is_empty() {
+            return;
+        }
+
+        let positions = vertices.map(|v| self.viewport_transform(v.position));
+        let inv_w = vertices.map(|v| 1.0 / v.position.w);
+
         let mut bboxmin = Vec2::new(f32::MAX, f32::MAX);
         let mut bboxmax = Vec2::new(f32::MIN, f32::MIN);
-        let clamp = Vec2::new(f32::MAX, f32::MAX);
+        let lower = Vec2::new(bounds.x as f32, bounds.y as f32);
+        let upper = lower
+            + Vec2::new((bounds.width - 1) as f32, (bounds.height - 1) as f32);
 
         // Calculate bounding box for triangle
-        for i in 0..3 {
+        for position in &positions {
             for j in 0..2 {
-                bboxmin[j] = 0.0_f32.max(bboxmin[j].min(positions[i][j]));
-                bboxmax[j] = clamp[j].min(bboxmax[j].max(positions[i][j]));
-            }
-        }
-
-        // Go through every pixel in the bounding box of the triangle and calculate
-        // the barycentric coordinates for the pixel. If the barycentric coordinates
-        // are non negative, the pixel is on the triangle and will be drawn
-        for x in bboxmin.x as i32..=bboxmax.x as i32 {
-            for y in bboxmin.y as i32..=bboxmax.y as i32 {
-                let bc_screen = barycentric(
-                    positions[0],
-                    positions[1],
-                    positions[2],
-                    Vec3::new(x as f32, y as f32, 0.0),
-                );
-                if bc_screen.x < 0.0 || bc_screen.y < 0.0 || bc_screen.z < 0.0 {
-                    continue;
-                }
-                // Calculate the z value for the depth test
-                let mut z = 0.0;
-                for i in 0..3 {
-                    z += positions[i][2] * bc_screen[i];
-                }
+                bboxmin[j] = lower[j].max(bboxmin[j].min(position[j]));
+                bboxmax[j] = upper[j].min(bboxmax[j].max(position[j]));
+            }
+        }
+
+        let format = self.framebuffer.depth_format();
+        let z_min = positions.iter().map(|p| p.z).fold(f32::MAX, f32::min);
+        let z_max = positions.iter().map(|p| p.z).fold(f32::MIN, f32::max);
+        let (z_min, z_max) = (format.quantize(z_min), format.quantize(z_max));
+        let greater_wins = self.occlusion_culling();
+
+        // The bounding box is walked in tiles of the depth pyramid. Tiles
+        // where everything is closer than the triangle are skipped, in tiles
+        // where everything is further away the depth test always passes.
+        let (x_start, x_end) = (bboxmin.x as u32, bboxmax.x as i32);
+        let (y_start, y_end) = (bboxmin.y as u32, bboxmax.y as i32);
+        if x_end < 0 || y_end < 0 {
+            return;
+        }
+        let (x_end, y_end) = (x_end as u32, y_end as u32);
 
-                // Is triangle on screen?
-                if x < 0
-                    || y < 0
-                    || x >= self.framebuffer.width() as i32
-                    || y >= self.framebuffer.height() as i32
+        for tile_x in x_start / HIZ_TILE_SIZE..=x_end / HIZ_TILE_SIZE {
+            for tile_y in y_start / HIZ_TILE_SIZE..=y_end / HIZ_TILE_SIZE {
+                let mut depth_test = true;
+                if let (Some(depth_pyramid), Some(greater_wins)) =
+                    (&mut self.depth_pyramid, greater_wins)
                 {
-                    println!("Discard");
-                    continue;
+                    let (tile_min, tile_max) = depth_pyramid.tile_depth(
+                        &self.framebuffer,
+                        tile_x,
+                        tile_y,
+                    );
+                    let (occluded, always_closer) = if greater_wins {
+                        (z_max < tile_min, z_min > tile_max)
+                    } else {
+                        (z_min > tile_max, z_max < tile_min)
+                    };
+                    if occluded {
+                        self.stats.tiles_occluded += 1;
+                        continue;
+                    }
+                    depth_test = !always_closer;
                 }
 
-                // Depth test
-                let pos = (x as u32, y as u32);
+                let x_range = (tile_x * HIZ_TILE_SIZE).max(x_start)
+                    ..=((tile_x + 1) * HIZ_TILE_SIZE - 1).min(x_end);
+                let y_range = (tile_y * HIZ_TILE_SIZE).max(y_start)
+                    ..=((tile_y + 1) * HIZ_TILE_SIZE - 1).min(y_end);
 
-                if self.framebuffer.depth(pos) < z {
-                    self.framebuffer.set_depth(pos, z);
-
-                    // Calculate the texture coordinate
-                    let mut tex_coord = Vec2::ZERO;
-                    for i in 0..3 {
-                        tex_coord += tex_coords[i] * bc_screen[i];
+                // Go through every pixel in the tile and calculate the
+                // barycentric coordinates for the pixel. If the barycentric
+                // coordinates are non negative, the pixel is on the triangle
+                // and will be drawn
+                for x in x_range {
+                    for y in y_range.clone() {
+                        self.shade_pixel(
+                            (x, y),
+                            &vertices,
+                            &positions,
+                            inv_w,
+                            depth_test,
+                            shader,
+                        );
                     }
-                    let color = texture.color(tex_coord);
-
-                    self.set_pixel(
-                        pos,
-                        color,
-                        // (
-                        //     (tex_coord.x * 255.0) as u8,
-                        //     (tex_coord.y * 255.0) as u8,
-                        //     0,
-                        // ),
-                    );
                 }
             }
         }
     }
 
-    /// Sets the pixel at the given position to the specified color
+    /// Runs the stencil and depth test and the fragment shader for a pixel if
+    /// it is covered by the triangle. The stencil operations for failed tests
+    /// are applied before the fragment shader runs, a discarded fragment only
+    /// skips the pass operation.
+    fn shade_pixel<S: Shader>(
+        &mut self,
+        pos: PixelPosition,
+        vertices: &[VertexOutput; 3],
+        positions: &[Vec3; 3],
+        inv_w: [f32; 3],
+        depth_test: bool,
+        shader: &S,
+    ) {
+        let bc_screen = barycentric(
+            positions[0],
+            positions[1],
+            positions[2],
+            Vec3::new(pos.0 as f32, pos.1 as f32, 0.0),
+        );
+        if bc_screen.x < 0.0 || bc_screen.y < 0.0 || bc_screen.z < 0.0 {
+            return;
+        }
+        // Calculate the z value for the depth test
+        let mut z = 0.0;
+        for (position, w) in positions.iter().zip(bc_screen.to_array()) {
+            z += position.z * w;
+        }
+
+        // Stencil test
+        let stencil_state = self.stencil_state;
+        let stencil = self.framebuffer.stencil(pos);
+        if !stencil_state.test(stencil) {
+            let stencil = stencil_state.apply(stencil_state.fail, stencil);
+            self.framebuffer.set_stencil(pos, stencil);
+            return;
+        }
+
+        // Depth test
+        let z = self.framebuffer.depth_format().quantize(z);
+        if depth_test
+            && !self
+                .depth_state
+                .compare
+                .test(z, self.framebuffer.depth(pos))
+        {
+            let stencil =
+                stencil_state.apply(stencil_state.depth_fail, stencil);
+            self.framebuffer.set_stencil(pos, stencil);
+            return;
+        }
+
+        // Interpolate the varyings perspective correct
+        let bc_clip = bc_screen * Vec3::from(inv_w);
+        let bc_clip = bc_clip / (bc_clip.x + bc_clip.y + bc_clip.z);
+        let varyings = Varyings::interpolate(
+            vertices.each_ref().map(|v| &v.varyings),
+            bc_clip,
+        );
+        let mut outputs = std::mem::take(&mut self.fragment_outputs);
+        outputs.clear();
+        outputs.resize(self.framebuffer.color_attachments().len(), Vec4::ZERO);
+        if let Some(color) = shader.fragment_outputs(&varyings, &mut outputs) {
+            if self.depth_state.write {
+                self.framebuffer.set_depth(pos, z);
+                if let Some(depth_pyramid) = &mut self.depth_pyramid {
+                    depth_pyramid.mark_dirty(pos);
+                }
+            }
+            let stencil = stencil_state.apply(stencil_state.pass, stencil);
+            self.framebuffer.set_stencil(pos, stencil);
+            if self.color_write {
+                let color = self.apply_fog(color, varyings.position);
+                self.set_pixel_linear(pos, color);
+                let pos = self.origin_pos(pos);
+                for (attachment, &value) in outputs.iter().enumerate() {
+                    self.framebuffer.set_attachment(attachment, pos, value);
+                }
+            }
+        }
+        self.fragment_outputs = outputs;
+    }
+
+    /// Sets the pixel at the given position to the specified color. With an
+    /// HDR target the color is decoded to linear and written to it.
     pub fn set_pixel(&mut self, pos: PixelPosition, color: Color) {
-        match self.draw_origin {
-            DrawOrigin::TopLeft => {
-                self.framebuffer.set_color_rgb(pos, color);
+        let pos = self.origin_pos(pos);
+        if self.framebuffer.hdr_format().is_some() {
+            self.framebuffer.set_hdr(pos, color_to_linear(color));
+        } else {
+            self.framebuffer.set_color_rgb(pos, color);
+        }
+    }
+
+    /// Sets the pixel at the given position to the specified linear color.
+    /// Without an HDR target the color is clamped and encoded as sRGB.
+    pub fn set_pixel_linear(&mut self, pos: PixelPosition, color: Vec3) {
+        let pos = self.origin_pos(pos);
+        if self.framebuffer.hdr_format().is_some() {
+            self.framebuffer.set_hdr(pos, color);
+        } else {
+            self.framebuffer.set_color_rgb(pos, linear_to_color(color));
+        }
+    }
+
+    /// Returns the linear color at the given position, from the HDR target
+    /// or decoded from the color buffer
+    pub fn pixel_linear(&self, pos: PixelPosition) -> Vec3 {
+        let pos = self.origin_pos(pos);
+        match self.framebuffer.hdr(pos) {
+            Some(color) => color,
+            None => color_to_linear(self.framebuffer.color_rgb(pos)),
+        }
+    }
+
+    /// Returns the value of the depth buffer at the given position
+    pub fn depth(&self, pos: PixelPosition) -> f32 {
+        self.framebuffer.depth(pos)
+    }
+
+    /// Returns the depth values row by row, in the same orientation as the
+    /// color buffer
+    pub fn depth_values(&self) -> Vec<f32> {
+        let width = self.framebuffer.width();
+        let height = self.framebuffer.height();
+        let mut values = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                // Converting to and from the origin is the same mapping
+                values.push(self.depth(self.origin_pos((x, y))));
             }
+        }
+        values
+    }
+
+    /// Copies the depth buffer into a grayscale image, in the same
+    /// orientation as the color buffer. The range of the drawn depth
+    /// values is stretched from black to white, pixels at the clear value
+    /// are clamped to the far end.
+    pub fn depth_to_luma_image(&self) -> image::GrayImage {
+        let values = self.depth_values();
+        let clear_value = self.depth_state.clear_value();
+        let (min, max) = values
+            .iter()
+            .filter(|&&depth| depth.is_finite() && depth != clear_value)
+            .fold((f32::MAX, f32::MIN), |(min, max), &depth| {
+                (min.min(depth), max.max(depth))
+            });
+        let range = (max - min).max(f32::MIN_POSITIVE);
+        let pixels = values
+            .iter()
+            .map(|depth| ((depth - min) / range).clamp(0.0, 1.0) * 255.0)
+            .map(|value| value.round() as u8)
+            .collect();
+        image::GrayImage::from_raw(
+            self.framebuffer.width(),
+            self.framebuffer.height(),
+            pixels,
+        )
+        .unwrap()
+    }
+
+    /// Writes the depth buffer to a file. RAW files get the depth values as
+    /// little endian 32 bit floats row by row and EXR files get them in
+    /// every channel. Every other format gets the normalized grayscale
+    /// image of [`Rasterizer::depth_to_luma_image`].
+    pub fn save_depth(&self, file_path: &str) -> Result<()> {
+        let width = self.framebuffer.width();
+        let height = self.framebuffer.height();
+        if has_extension(file_path, "raw") {
+            let bytes: Vec<u8> = self
+                .depth_values()
+                .iter()
+                .flat_map(|depth| depth.to_le_bytes())
+                .collect();
+            std::fs::write(file_path, bytes)?;
+        } else if has_extension(file_path, "exr") {
+            let pixels = self
+                .depth_values()
+                .iter()
+                .flat_map(|&depth| [depth; 3])
+                .collect();
+            image::Rgb32FImage::from_raw(width, height, pixels)
+                .unwrap()
+                .save(file_path)?;
+        } else {
+            // Formats like PPM only store color
+            let image =
+                image::DynamicImage::ImageLuma8(self.depth_to_luma_image());
+            if has_extension(file_path, "ppm") {
+                image.to_rgb8().save(file_path)?;
+            } else {
+                image.save(file_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value of a color attachment at the given position
+    pub fn attachment(&self, attachment: usize, pos: PixelPosition) -> Vec4 {
+        self.framebuffer
+            .attachment(attachment, self.origin_pos(pos))
+    }
+
+    /// Converts a position relative to the draw origin to a position in the
+    /// color buffer and the color attachments
+    fn origin_pos(&self, pos: PixelPosition) -> PixelPosition {
+        match self.draw_origin {
+            DrawOrigin::TopLeft => pos,
             DrawOrigin::BottomLeft => {
                 let height = self.framebuffer.height();
-                let pos = (pos.0, (height - 1) - pos.1);
-                self.framebuffer.set_color_rgb(pos, color);
+                (pos.0, (height - 1) - pos.1)
             }
-        };
+        }
     }
 
     /// Returns a reference to the framebuffer
     pub fn buffer(&self) -> &[u32] {
-        &self.framebuffer.color_buffer()
+        self.framebuffer.color_buffer()
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+}
+
+/// Clips a line to the rectangle from `min` to `max` with the Liang-Barsky
+/// algorithm. Returns `None` if the line is completely outside.
+pub(crate) fn clip_line(
+    start: Vec2,
+    end: Vec2,
+    min: Vec2,
+    max: Vec2,
+) -> Option<(Vec2, Vec2)> {
+    let delta = end - start;
+    let mut t0: f32 = 0.0;
+    let mut t1: f32 = 1.0;
+    // Each edge as the rate the line moves towards its outside and the
+    // distance from the start to it
+    let edges = [
+        (-delta.x, start.x - min.x),
+        (delta.x, max.x - start.x),
+        (-delta.y, start.y - min.y),
+        (delta.y, max.y - start.y),
+    ];
+    for (p, q) in edges {
+        if p == 0.0 {
+            // Parallel to the edge
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    Some((start + delta * t0, start + delta * t1))
+}
+
+/// Clips a triangle in clip space against the near plane. Returns a convex
+/// polygon with up to four vertices, which is empty if the triangle is
+/// completely behind the near plane.
+/// `near_distance` is the signed distance of a position to the near plane.
+fn clip_near(
+    vertices: &[VertexOutput; 3],
+    near_distance: impl Fn(Vec4) -> f32,
+) -> Vec<VertexOutput> {
+    let distance = |v: &VertexOutput| near_distance(v.position);
+
+    let mut polygon = Vec::with_capacity(4);
+    for i in 0..3 {
+        let current = &vertices[i];
+        let next = &vertices[(i + 1) % 3];
+        let d_current = distance(current);
+        let d_next = distance(next);
+
+        if d_current >= 0.0 {
+            polygon.push(*current);
+        }
+        if (d_current >= 0.0) != (d_next >= 0.0) {
+            let t = d_current / (d_current - d_next);
+            polygon.push(current.lerp(next, t));
+        }
     }
+    polygon
 }
 
 /// Calculates the barycentric coordinates for the given points
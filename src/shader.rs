@@ -0,0 +1,388 @@
+use crate::prelude::*;
+
+/// Values that are computed per vertex and interpolated across a triangle
+#[derive(Default, Copy, Clone, Debug)]
+pub struct Varyings {
+    /// Position in world space
+    pub position: Vec3,
+    pub tex_coord: Vec2,
+    pub normal: Vec3,
+    pub tangent: Vec4,
+}
+
+impl Varyings {
+    /// Interpolates the varyings of a triangle with the given barycentric
+    /// coordinates
+    pub fn interpolate(varyings: [&Varyings; 3], bc: Vec3) -> Self {
+        let mut result = Self::default();
+        for (v, w) in varyings.iter().zip(bc.to_array()) {
+            result.position += v.position * w;
+            result.tex_coord += v.tex_coord * w;
+            result.normal += v.normal * w;
+            result.tangent += v.tangent * w;
+        }
+        result
+    }
+
+    /// Passes the attributes of a vertex through
+    pub fn from_vertex(vertex: &Vertex) -> Self {
+        Self {
+            position: vertex.position,
+            tex_coord: vertex.tex_coord,
+            normal: vertex.normal,
+            tangent: vertex.tangent,
+        }
+    }
+
+    /// Linearly interpolates between two sets of varyings
+    pub fn lerp(&self, other: &Varyings, t: f32) -> Self {
+        Self::interpolate([self, other, other], Vec3::new(1.0 - t, t, 0.0))
+    }
+}
+
+/// The result of running the vertex shader on a single vertex
+#[derive(Default, Copy, Clone, Debug)]
+pub struct VertexOutput {
+    /// Position of the vertex in clip space
+    pub position: Vec4,
+    pub varyings: Varyings,
+}
+
+impl VertexOutput {
+    /// Linearly interpolates position and varyings between two vertices
+    pub fn lerp(&self, other: &VertexOutput, t: f32) -> Self {
+        Self {
+            position: self.position.lerp(other.position, t),
+            varyings: self.varyings.lerp(&other.varyings, t),
+        }
+    }
+}
+
+/// A shader transforms vertices and computes the color of each fragment
+pub trait Shader {
+    /// Transforms a vertex into clip space
+    fn vertex(&self, vertex: &Vertex) -> VertexOutput;
+
+    /// Returns the sRGB color of a fragment or `None` if it should be
+    /// discarded
+    fn fragment(&self, varyings: &Varyings) -> Option<Color>;
+
+    /// Returns the linear color of a fragment, which may exceed 1 when
+    /// drawing into an HDR target, and writes the values for the color
+    /// attachments of the framebuffer into `outputs`. `outputs` has one value
+    /// per attachment and starts out zeroed. By default only the color of
+    /// [`Shader::fragment`] is decoded.
+    fn fragment_outputs(
+        &self,
+        varyings: &Varyings,
+        outputs: &mut [Vec4],
+    ) -> Option<Vec3> {
+        let _ = outputs;
+        self.fragment(varyings).map(color_to_linear)
+    }
+}
+
+/// Shades a mesh with the diffuse texture of its material. If the material
+/// has no texture the diffuse color is used.
+pub struct TextureShader<'a> {
+    transform: Mat4,
+    material: &'a Material,
+}
+
+impl<'a> TextureShader<'a> {
+    pub fn new(transform: Mat4, material: &'a Material) -> Self {
+        Self {
+            transform,
+            material,
+        }
+    }
+}
+
+impl<'a> Shader for TextureShader<'a> {
+    fn vertex(&self, vertex: &Vertex) -> VertexOutput {
+        VertexOutput {
+            position: self.transform * vertex.position.extend(1.0),
+            varyings: Varyings::from_vertex(vertex),
+        }
+    }
+
+    fn fragment(&self, varyings: &Varyings) -> Option<Color> {
+        if !alpha_test(self.material, varyings.tex_coord) {
+            return None;
+        }
+        Some(diffuse_color(self.material, varyings.tex_coord))
+    }
+}
+
+/// Shades a mesh like the [`TextureShader`] and lights it with a list of
+/// lights on top of an ambient term using the interpolated vertex normals.
+/// If the material has a normal map, the normals are perturbed in tangent
+/// space. With an environment the material reflects it by its reflectivity.
+pub struct LitShader<'a> {
+    transform: Mat4,
+    material: &'a Material,
+    lights: &'a [Light],
+    ambient: Vec3,
+    /// Cubemap that is reflected and the camera position
+    environment: Option<(&'a Cubemap, Vec3)>,
+    ambient_occlusion: Option<&'a AoMap>,
+}
+
+impl<'a> LitShader<'a> {
+    pub fn new(
+        transform: Mat4,
+        material: &'a Material,
+        lights: &'a [Light],
+        ambient: Vec3,
+    ) -> Self {
+        Self {
+            transform,
+            material,
+            lights,
+            ambient,
+            environment: None,
+            ambient_occlusion: None,
+        }
+    }
+
+    /// Scales the ambient light by the occlusion of the pixel. The map has
+    /// to be computed for the transform of the shader.
+    pub fn with_ambient_occlusion(mut self, ao_map: &'a AoMap) -> Self {
+        self.ambient_occlusion = Some(ao_map);
+        self
+    }
+
+    /// Reflects the cubemap as seen from the camera position
+    pub fn with_environment(
+        mut self,
+        cubemap: &'a Cubemap,
+        camera_position: Vec3,
+    ) -> Self {
+        self.environment = Some((cubemap, camera_position));
+        self
+    }
+}
+
+impl<'a> Shader for LitShader<'a> {
+    fn vertex(&self, vertex: &Vertex) -> VertexOutput {
+        VertexOutput {
+            position: self.transform * vertex.position.extend(1.0),
+            varyings: Varyings::from_vertex(vertex),
+        }
+    }
+
+    fn fragment(&self, varyings: &Varyings) -> Option<Color> {
+        self.fragment_outputs(varyings, &mut [])
+            .map(linear_to_color)
+    }
+
+    fn fragment_outputs(
+        &self,
+        varyings: &Varyings,
+        _outputs: &mut [Vec4],
+    ) -> Option<Vec3> {
+        if !alpha_test(self.material, varyings.tex_coord) {
+            return None;
+        }
+        let normal = shading_normal(self.material, varyings);
+        let occlusion =
+            ambient_occlusion(self.ambient_occlusion, self.transform, varyings);
+        let mut color = shade_lambert(
+            albedo(self.material, varyings.tex_coord),
+            varyings.position,
+            normal,
+            self.ambient * occlusion,
+            self.lights,
+        );
+        if let Some((cubemap, camera_position)) = self.environment {
+            let reflectivity = self.material.reflectivity(varyings.tex_coord);
+            if reflectivity > 0.0 {
+                let incident = varyings.position - camera_position;
+                let reflection = incident - 2.0 * incident.dot(normal) * normal;
+                color = color.lerp(cubemap.sample(reflection), reflectivity);
+            }
+        }
+        Some(color)
+    }
+}
+
+/// Shades a mesh with the metallic-roughness model of its material, see
+/// [`shade_pbr`]. An environment adds image based lighting.
+pub struct PbrShader<'a> {
+    transform: Mat4,
+    material: &'a Material,
+    camera_position: Vec3,
+    lights: &'a [Light],
+    ambient: Vec3,
+    environment: Option<&'a Environment>,
+    ambient_occlusion: Option<&'a AoMap>,
+}
+
+impl<'a> PbrShader<'a> {
+    pub fn new(
+        transform: Mat4,
+        material: &'a Material,
+        camera_position: Vec3,
+        lights: &'a [Light],
+        ambient: Vec3,
+        environment: Option<&'a Environment>,
+    ) -> Self {
+        Self {
+            transform,
+            material,
+            camera_position,
+            lights,
+            ambient,
+            environment,
+            ambient_occlusion: None,
+        }
+    }
+
+    /// Scales the indirect light by the occlusion of the pixel. The map has
+    /// to be computed for the transform of the shader.
+    pub fn with_ambient_occlusion(mut self, ao_map: &'a AoMap) -> Self {
+        self.ambient_occlusion = Some(ao_map);
+        self
+    }
+}
+
+impl<'a> Shader for PbrShader<'a> {
+    fn vertex(&self, vertex: &Vertex) -> VertexOutput {
+        VertexOutput {
+            position: self.transform * vertex.position.extend(1.0),
+            varyings: Varyings::from_vertex(vertex),
+        }
+    }
+
+    fn fragment(&self, varyings: &Varyings) -> Option<Color> {
+        self.fragment_outputs(varyings, &mut [])
+            .map(linear_to_color)
+    }
+
+    fn fragment_outputs(
+        &self,
+        varyings: &Varyings,
+        _outputs: &mut [Vec4],
+    ) -> Option<Vec3> {
+        let tex_coord = varyings.tex_coord;
+        if !alpha_test(self.material, tex_coord) {
+            return None;
+        }
+        let surface = PbrSurface {
+            albedo: albedo(self.material, tex_coord),
+            metallic: self.material.metallic(tex_coord),
+            roughness: self.material.roughness(tex_coord),
+            specular: self.material.specular(tex_coord),
+            position: varyings.position,
+            normal: shading_normal(self.material, varyings),
+            occlusion: ambient_occlusion(
+                self.ambient_occlusion,
+                self.transform,
+                varyings,
+            ),
+        };
+        let view =
+            (self.camera_position - varyings.position).normalize_or_zero();
+        Some(shade_pbr(
+            &surface,
+            view,
+            self.lights,
+            self.ambient,
+            self.environment,
+        ))
+    }
+}
+
+/// Draws a mesh in a single color, pushed outwards along the vertex normals.
+/// Drawn after the mesh where the stencil buffer isn't marked, only an outline
+/// remains.
+pub struct OutlineShader {
+    transform: Mat4,
+    width: f32,
+    color: Color,
+}
+
+impl OutlineShader {
+    /// Creates a new shader. `width` is in object space units.
+    pub fn new(transform: Mat4, width: f32, color: Color) -> Self {
+        Self {
+            transform,
+            width,
+            color,
+        }
+    }
+}
+
+impl Shader for OutlineShader {
+    fn vertex(&self, vertex: &Vertex) -> VertexOutput {
+        let position = vertex.position + vertex.normal * self.width;
+        VertexOutput {
+            position: self.transform * position.extend(1.0),
+            varyings: Varyings::default(),
+        }
+    }
+
+    fn fragment(&self, _varyings: &Varyings) -> Option<Color> {
+        Some(self.color)
+    }
+}
+
+/// Returns the diffuse texture color of the material or the diffuse color if
+/// it has no texture
+pub(crate) fn diffuse_color(material: &Material, tex_coord: Vec2) -> Color {
+    match material.diffuse_texture() {
+        Some(texture) => texture.color(tex_coord),
+        None => material.diffuse_color(),
+    }
+}
+
+/// Returns the linear albedo of the material, from the diffuse texture or
+/// the diffuse color
+pub(crate) fn albedo(material: &Material, tex_coord: Vec2) -> Vec3 {
+    match material.diffuse_texture() {
+        Some(texture) => texture.rgba(tex_coord).truncate(),
+        None => color_to_linear(material.diffuse_color()),
+    }
+}
+
+/// Returns the ambient occlusion at the pixel of a fragment, or 1 without a
+/// map. `transform` projects the world position to clip space.
+pub(crate) fn ambient_occlusion(
+    ao_map: Option<&AoMap>,
+    transform: Mat4,
+    varyings: &Varyings,
+) -> f32 {
+    match ao_map {
+        Some(ao_map) => {
+            let ndc = transform.project_point3(varyings.position);
+            ao_map.sample_ndc(ndc.truncate())
+        }
+        None => 1.0,
+    }
+}
+
+/// Returns false if the fragment is discarded by the alpha cutoff of the
+/// material
+pub(crate) fn alpha_test(material: &Material, tex_coord: Vec2) -> bool {
+    match (material.alpha_cutoff(), material.diffuse_texture()) {
+        (Some(cutoff), Some(texture)) => texture.rgba(tex_coord).w >= cutoff,
+        _ => true,
+    }
+}
+
+/// Returns the normal used for lighting. If the material has a normal map,
+/// the normal from the map is transformed from tangent space.
+pub(crate) fn shading_normal(material: &Material, varyings: &Varyings) -> Vec3 {
+    let normal = varyings.normal;
+    let tangent = varyings.tangent.truncate();
+    let normal_texture = match material.normal_texture() {
+        Some(texture) if tangent != Vec3::ZERO => texture,
+        _ => return normal.normalize_or_zero(),
+    };
+
+    // MikkTSpace expects the interpolated tangent frame to be used without
+    // normalization or orthogonalization, so the baked normals match exactly
+    let bitangent = varyings.tangent.w.signum() * normal.cross(tangent);
+    let n = normal_texture.normal(varyings.tex_coord);
+    (tangent * n.x + bitangent * n.y + normal * n.z).normalize_or_zero()
+}
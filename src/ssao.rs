@@ -0,0 +1,307 @@
+use crate::prelude::*;
+
+/// Width and height of the tiled random rotations, the blur averages over
+/// the same size so the pattern disappears
+const NOISE_SIZE: u32 = 4;
+
+/// Screen space ambient occlusion. Every pixel tests a hemisphere of samples
+/// around its view space position against the depth buffer, so surfaces in
+/// creases and corners receive less ambient light.
+pub struct Ssao {
+    /// View space radius of the sampled hemisphere
+    pub radius: f32,
+    /// Depth difference below which a sample doesn't occlude, against self
+    /// occlusion of flat surfaces
+    pub bias: f32,
+    /// Exponent applied to the result, larger values darken more
+    pub power: f32,
+    /// Sample offsets in a unit hemisphere around +Z, denser near the center
+    kernel: Vec<Vec3>,
+    /// Random vectors in the XY plane that rotate the kernel per pixel
+    noise: Vec<Vec3>,
+}
+
+/// Ambient occlusion of every pixel, 1 is unoccluded. Positions are relative
+/// to the draw origin like the ones of the rasterizer.
+pub struct AoMap {
+    width: u32,
+    height: u32,
+    data: Vec<f32>,
+}
+
+impl Ssao {
+    /// Creates the pass with a kernel of `sample_count` samples
+    pub fn new(sample_count: u32) -> Self {
+        let kernel = (0..sample_count)
+            .map(|i| {
+                let direction = Vec3::new(
+                    random(i * 4) * 2.0 - 1.0,
+                    random(i * 4 + 1) * 2.0 - 1.0,
+                    random(i * 4 + 2),
+                )
+                .normalize_or_zero();
+
+                // Most samples close to the origin
+                let t = i as f32 / sample_count as f32;
+                let scale = 0.1 + 0.9 * t * t;
+                direction * random(i * 4 + 3) * scale
+            })
+            .collect();
+        let noise = (0..NOISE_SIZE * NOISE_SIZE)
+            .map(|i| {
+                let seed = 0x1000 + i * 2;
+                Vec3::new(
+                    random(seed) * 2.0 - 1.0,
+                    random(seed + 1) * 2.0 - 1.0,
+                    0.0,
+                )
+                .try_normalize()
+                .unwrap_or(Vec3::X)
+            })
+            .collect();
+        Self {
+            radius: 0.1,
+            bias: 0.005,
+            power: 1.5,
+            kernel,
+            noise,
+        }
+    }
+
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn with_power(mut self, power: f32) -> Self {
+        self.power = power;
+        self
+    }
+
+    /// Computes the blurred occlusion from the depth buffer of the
+    /// rasterizer, which has to be drawn with the projection of the camera.
+    /// If `normal_attachment` is the index of a color attachment with world
+    /// space normals they are used, otherwise normals are reconstructed from
+    /// the depth.
+    pub fn compute(
+        &self,
+        rasterizer: &Rasterizer,
+        camera: &Camera,
+        normal_attachment: Option<usize>,
+    ) -> AoMap {
+        let width = rasterizer.framebuffer().width();
+        let height = rasterizer.framebuffer().height();
+        let proj = camera.proj_mat();
+        let view = camera.view_mat();
+        let depth_view = DepthView::new(rasterizer, proj);
+
+        let mut occlusion = AoMap::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let Some(position) = depth_view.position(x as i32, y as i32)
+                else {
+                    continue;
+                };
+                let normal = match normal_attachment {
+                    Some(attachment) => view.transform_vector3(
+                        rasterizer.attachment(attachment, (x, y)).truncate(),
+                    ),
+                    None => depth_view.normal(x as i32, y as i32, position),
+                }
+                .normalize_or_zero();
+                if normal == Vec3::ZERO {
+                    continue;
+                }
+
+                // Tangent frame around the normal, rotated by the noise
+                let noise = self.noise[((x % NOISE_SIZE)
+                    + (y % NOISE_SIZE) * NOISE_SIZE)
+                    as usize];
+                let tangent = (noise - normal * noise.dot(normal))
+                    .try_normalize()
+                    .unwrap_or_else(|| normal.any_orthonormal_vector());
+                let bitangent = normal.cross(tangent);
+
+                let mut occluded = 0.0;
+                for offset in &self.kernel {
+                    let sample = position
+                        + (tangent * offset.x
+                            + bitangent * offset.y
+                            + normal * offset.z)
+                            * self.radius;
+                    let Some(scene_depth) = depth_view.depth_at(sample) else {
+                        continue;
+                    };
+                    // Ignore occluders far outside of the radius
+                    let range = (self.radius
+                        / (position.z - scene_depth).abs())
+                    .clamp(0.0, 1.0);
+                    let range = range * range * (3.0 - 2.0 * range);
+                    if scene_depth >= sample.z + self.bias {
+                        occluded += range;
+                    }
+                }
+                let ao = 1.0 - occluded / self.kernel.len().max(1) as f32;
+                occlusion.set((x, y), ao.max(0.0).powf(self.power));
+            }
+        }
+        occlusion.blurred()
+    }
+}
+
+impl AoMap {
+    /// Creates a map without occlusion
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![1.0; (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, pos: PixelPosition) -> f32 {
+        self.data[(pos.0 + pos.1 * self.width) as usize]
+    }
+
+    pub fn set(&mut self, pos: PixelPosition, value: f32) {
+        self.data[(pos.0 + pos.1 * self.width) as usize] = value;
+    }
+
+    /// Returns the occlusion of the pixel that contains the normalized
+    /// device coordinate
+    pub fn sample_ndc(&self, ndc: Vec2) -> f32 {
+        let pos = (ndc + 1.0)
+            * 0.5
+            * Vec2::new(self.width as f32, self.height as f32);
+        let x = (pos.x.round().max(0.0) as u32).min(self.width - 1);
+        let y = (pos.y.round().max(0.0) as u32).min(self.height - 1);
+        self.get((x, y))
+    }
+
+    /// Averages blocks of the noise size to remove the noise pattern
+    fn blurred(&self) -> Self {
+        let mut result = Self::new(self.width, self.height);
+        let half = (NOISE_SIZE / 2) as i32;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut sum = 0.0;
+                let mut count = 0;
+                for dy in -half..half {
+                    for dx in -half..half {
+                        let sx = x as i32 + dx;
+                        let sy = y as i32 + dy;
+                        if sx >= 0
+                            && sy >= 0
+                            && sx < self.width as i32
+                            && sy < self.height as i32
+                        {
+                            sum += self.get((sx as u32, sy as u32));
+                            count += 1;
+                        }
+                    }
+                }
+                result.set((x, y), sum / count as f32);
+            }
+        }
+        result
+    }
+}
+
+/// Reconstructs view space positions from the depth buffer
+struct DepthView<'a> {
+    rasterizer: &'a Rasterizer,
+    proj: Mat4,
+    inv_proj: Mat4,
+    size: Vec2,
+}
+
+impl<'a> DepthView<'a> {
+    fn new(rasterizer: &'a Rasterizer, proj: Mat4) -> Self {
+        let framebuffer = rasterizer.framebuffer();
+        Self {
+            rasterizer,
+            proj,
+            inv_proj: proj.inverse(),
+            size: Vec2::new(
+                framebuffer.width() as f32,
+                framebuffer.height() as f32,
+            ),
+        }
+    }
+
+    /// Returns the view space position of a pixel or `None` if nothing was
+    /// drawn there
+    fn position(&self, x: i32, y: i32) -> Option<Vec3> {
+        if x < 0 || y < 0 || x >= self.size.x as i32 || y >= self.size.y as i32
+        {
+            return None;
+        }
+        let depth = self.rasterizer.depth((x as u32, y as u32));
+        let depth_state = self.rasterizer.depth_state();
+        if depth == depth_state.clear_value() {
+            return None;
+        }
+        // Reversed Z stores the clip space depth as it is
+        let ndc_z = if depth_state.reversed_z {
+            depth
+        } else {
+            depth * 2.0 - 1.0
+        };
+        let ndc = Vec2::new(x as f32, y as f32) / self.size * 2.0 - 1.0;
+        Some(self.inv_proj.project_point3(ndc.extend(ndc_z)))
+    }
+
+    /// Reconstructs the normal from the neighbors with the smaller depth
+    /// difference on each axis, so edges don't bend it
+    fn normal(&self, x: i32, y: i32, position: Vec3) -> Vec3 {
+        let closest = |a: Option<Vec3>, b: Option<Vec3>| match (a, b) {
+            (Some(a), Some(b)) => {
+                if (a.z - position.z).abs() < (b.z - position.z).abs() {
+                    a - position
+                } else {
+                    position - b
+                }
+            }
+            (Some(a), None) => a - position,
+            (None, Some(b)) => position - b,
+            (None, None) => Vec3::ZERO,
+        };
+        let dx = closest(self.position(x + 1, y), self.position(x - 1, y));
+        let dy = closest(self.position(x, y + 1), self.position(x, y - 1));
+        let normal = dx.cross(dy).normalize_or_zero();
+        // Face the camera
+        if normal.dot(-position) < 0.0 {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    /// Returns the view space Z of the surface that is visible in the
+    /// direction of a view space point
+    fn depth_at(&self, point: Vec3) -> Option<f32> {
+        let ndc = self.proj.project_point3(point);
+        let pos = (ndc.truncate() + 1.0) * 0.5 * self.size;
+        self.position(pos.x.round() as i32, pos.y.round() as i32)
+            .map(|position| position.z)
+    }
+}
+
+/// Deterministic pseudo random number in [0, 1) for the given seed
+fn random(seed: u32) -> f32 {
+    let mut x = seed.wrapping_mul(0x9e37_79b9) ^ 0x85eb_ca6b;
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    (x >> 8) as f32 / (1 << 24) as f32
+}
@@ -0,0 +1,90 @@
+use crate::prelude::*;
+
+/// What happens to the stored stencil value after a stencil or depth test
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    /// Replace the value with the reference value
+    Replace,
+    /// Increment the value and clamp it to 255
+    IncrementClamp,
+    /// Decrement the value and clamp it to 0
+    DecrementClamp,
+    Invert,
+    /// Increment the value and wrap it to 0
+    IncrementWrap,
+    /// Decrement the value and wrap it to 255
+    DecrementWrap,
+}
+
+impl StencilOp {
+    /// Returns the new stencil value
+    pub fn apply(self, value: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => value,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::IncrementClamp => value.saturating_add(1),
+            StencilOp::DecrementClamp => value.saturating_sub(1),
+            StencilOp::Invert => !value,
+            StencilOp::IncrementWrap => value.wrapping_add(1),
+            StencilOp::DecrementWrap => value.wrapping_sub(1),
+        }
+    }
+}
+
+/// How fragments are tested against and written to the stencil buffer. The
+/// reference value is compared against the stored value, both masked with
+/// `read_mask`. The default state passes every fragment and never changes
+/// the stencil buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StencilState {
+    pub compare: CompareFunc,
+    pub reference: u8,
+    /// Bits that take part in the stencil test
+    pub read_mask: u8,
+    /// Bits of the stored value that the operations may change
+    pub write_mask: u8,
+    /// Operation if the stencil test fails
+    pub fail: StencilOp,
+    /// Operation if the stencil test passes but the depth test fails
+    pub depth_fail: StencilOp,
+    /// Operation if both tests pass
+    pub pass: StencilOp,
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        Self {
+            compare: CompareFunc::Always,
+            reference: 0,
+            read_mask: 0xff,
+            write_mask: 0xff,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+impl StencilState {
+    /// Returns true if the stored value passes the stencil test
+    pub fn test(&self, stored: u8) -> bool {
+        self.compare
+            .test(self.reference & self.read_mask, stored & self.read_mask)
+    }
+
+    /// Applies the operation to the stored value, keeping the bits outside
+    /// of the write mask
+    pub fn apply(&self, op: StencilOp, stored: u8) -> u8 {
+        let value = op.apply(stored, self.reference);
+        (stored & !self.write_mask) | (value & self.write_mask)
+    }
+
+    /// Returns true if fragments that fail a test still change the stencil
+    /// buffer, so they can't be skipped by occlusion culling
+    pub fn writes_on_fail(&self) -> bool {
+        self.fail != StencilOp::Keep || self.depth_fail != StencilOp::Keep
+    }
+}
@@ -4,26 +4,69 @@ type Image = image::ImageBuffer<image::Rgba<u8>, Vec<u8>>;
 
 pub struct Texture {
     image: Image,
+    /// The color channels are sRGB encoded and decoded when sampled
+    srgb: bool,
 }
 
 impl Texture {
     pub fn from_file(file_path: &str) -> Result<Self> {
         let tex = image::open(file_path)?;
         let image = tex.to_rgba8();
-        Ok(Self { image })
+        Ok(Self { image, srgb: false })
+    }
+
+    /// Marks the color channels as sRGB encoded, as for color textures.
+    /// Textures that store data like normals or roughness are linear.
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.srgb = srgb;
+        self
+    }
+
+    pub fn is_srgb(&self) -> bool {
+        self.srgb
     }
 
     pub fn color(&self, tex_coord: Vec2) -> Color {
+        let pixel = self.pixel(tex_coord);
+        (pixel[0], pixel[1], pixel[2])
+    }
+
+    /// Returns the linear color and alpha at the given texture coordinate
+    /// with channels in [0, 1]
+    pub fn rgba(&self, tex_coord: Vec2) -> Vec4 {
+        let pixel = self.pixel(tex_coord);
+        if self.srgb {
+            let [r, g, b, a] = pixel.0;
+            color_to_linear((r, g, b)).extend(a as f32 / 255.0)
+        } else {
+            Vec4::from_array(pixel.0.map(|c| c as f32 / 255.0))
+        }
+    }
+
+    /// Returns the first channel at the given texture coordinate in [0, 1].
+    /// Used for grayscale maps.
+    pub fn value(&self, tex_coord: Vec2) -> f32 {
+        self.pixel(tex_coord)[0] as f32 / 255.0
+    }
+
+    /// Returns the normal that is stored in the texture at the given texture
+    /// coordinate. The channels are mapped from [0, 255] to [-1, 1].
+    pub fn normal(&self, tex_coord: Vec2) -> Vec3 {
+        let (r, g, b) = self.color(tex_coord);
+        Vec3::new(r as f32, g as f32, b as f32) / 255.0 * 2.0 - Vec3::ONE
+    }
+
+    /// Returns the pixel at the texture coordinate. Coordinates outside of
+    /// [0, 1] are clamped to the edge.
+    fn pixel(&self, tex_coord: Vec2) -> &image::Rgba<u8> {
         let width = self.image.width() as f32;
         let height = self.image.height() as f32;
 
         let u = tex_coord.x;
         let v = tex_coord.y;
 
-        let x = (width * u) as u32;
-        let y = (height - (height * v)) as u32;
-        let pixel = self.image.get_pixel(x, y);
-
-        (pixel[0], pixel[1], pixel[2])
+        let x = ((width * u) as u32).min(self.image.width() - 1);
+        let y = ((height - (height * v)) as u32).min(self.image.height() - 1);
+        self.image.get_pixel(x, y)
     }
 }
@@ -0,0 +1,45 @@
+use crate::prelude::*;
+
+/// Curve that compresses HDR colors into the displayable range
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    /// Values above 1 are clipped
+    Clamp,
+    /// `c / (1 + c)` per channel
+    Reinhard,
+    /// Fit of the ACES filmic curve by Krzysztof Narkowicz
+    #[default]
+    Aces,
+}
+
+impl ToneMapOperator {
+    /// Maps a linear HDR color to [0, 1]
+    pub fn apply(self, color: Vec3) -> Vec3 {
+        let color = color.max(Vec3::ZERO);
+        let mapped = match self {
+            ToneMapOperator::Clamp => color,
+            ToneMapOperator::Reinhard => color / (color + 1.0),
+            ToneMapOperator::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                color * (a * color + b) / (color * (c * color + d) + e)
+            }
+        };
+        mapped.clamp(Vec3::ZERO, Vec3::ONE)
+    }
+}
+
+/// The final pass that turns the HDR color target into displayable colors
+#[derive(Default, Copy, Clone, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// Exposure adjustment in stops, every stop doubles the brightness
+    pub exposure: f32,
+}
+
+impl ToneMapping {
+    /// Exposes and tone maps a linear HDR color and encodes it as sRGB
+    pub fn map(&self, color: Vec3) -> Color {
+        let exposed = color * self.exposure.exp2();
+        linear_to_color(self.operator.apply(exposed))
+    }
+}
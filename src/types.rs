@@ -1,2 +1,59 @@
+use glam::Vec3;
+use std::sync::OnceLock;
+
 pub type Color = (u8, u8, u8);
 pub type PixelPosition = (u32, u32);
+
+/// Converts a color to a vector with channels in [0, 1]
+pub fn color_to_vec3(color: Color) -> Vec3 {
+    Vec3::new(color.0 as f32, color.1 as f32, color.2 as f32) / 255.0
+}
+
+/// Converts a vector with channels in [0, 1] to a color. Channels outside of
+/// the range are clamped.
+pub fn vec3_to_color(color: Vec3) -> Color {
+    let color = (color.clamp(Vec3::ZERO, Vec3::ONE) * 255.0).round();
+    (color.x as u8, color.y as u8, color.z as u8)
+}
+
+/// Decodes an sRGB encoded color with channels in [0, 1] to linear
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+    let decode = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Vec3::new(decode(color.x), decode(color.y), decode(color.z))
+}
+
+/// Encodes a linear color with channels in [0, 1] to sRGB
+pub fn linear_to_srgb(color: Vec3) -> Vec3 {
+    let encode = |c: f32| {
+        if c <= 0.003_130_8 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    Vec3::new(encode(color.x), encode(color.y), encode(color.z))
+}
+
+/// Converts an sRGB color to linear with channels in [0, 1]
+pub fn color_to_linear(color: Color) -> Vec3 {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        std::array::from_fn(|i| srgb_to_linear(Vec3::splat(i as f32 / 255.0)).x)
+    });
+    Vec3::new(
+        table[color.0 as usize],
+        table[color.1 as usize],
+        table[color.2 as usize],
+    )
+}
+
+/// Converts a linear color to sRGB. Channels outside of [0, 1] are clamped.
+pub fn linear_to_color(color: Vec3) -> Color {
+    vec3_to_color(linear_to_srgb(color.clamp(Vec3::ZERO, Vec3::ONE)))
+}
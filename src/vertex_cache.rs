@@ -0,0 +1,34 @@
+use crate::prelude::*;
+
+/// Caches the results of the vertex shader by vertex index, so that vertices
+/// shared between triangles are only transformed once per draw call.
+#[derive(Default)]
+pub struct VertexCache {
+    entries: Vec<Option<VertexOutput>>,
+}
+
+impl VertexCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Invalidates all entries and makes room for the given number of
+    /// vertices
+    pub fn reset(&mut self, vertex_count: usize) {
+        self.entries.clear();
+        self.entries.resize(vertex_count, None);
+    }
+
+    /// Returns the cached output for the given index. If there is none,
+    /// `shade` is called and its result is stored.
+    pub fn get_or_insert_with<F>(
+        &mut self,
+        index: u32,
+        shade: F,
+    ) -> VertexOutput
+    where
+        F: FnOnce() -> VertexOutput,
+    {
+        *self.entries[index as usize].get_or_insert_with(shade)
+    }
+}